@@ -1,4 +1,8 @@
-use std::sync::{Arc, Weak};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{Arc, Weak},
+};
 
 use futures_util::{
     stream::{SplitSink, SplitStream},
@@ -7,9 +11,9 @@ use futures_util::{
 use thiserror::Error;
 use tokio::{
     net::TcpStream,
-    sync::{broadcast, mpsc, oneshot, Mutex},
+    sync::{broadcast, mpsc, oneshot, watch, Mutex},
     task::JoinHandle,
-    time::{self, Duration},
+    time::{self, Duration, Instant},
 };
 use tokio_tungstenite::{
     tungstenite::{self, protocol::CloseFrame, Message as TMessage},
@@ -17,7 +21,11 @@ use tokio_tungstenite::{
 };
 use tracing::{debug, error, info, trace, warn};
 
+use crate::config;
+
 pub mod messages;
+#[cfg(test)]
+pub(crate) mod mock;
 pub mod requests;
 
 pub use messages::Message;
@@ -26,14 +34,14 @@ pub use requests::Request;
 pub type Writer = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, TMessage>;
 pub type Reader = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
 
-const BELABOX_WS: &str = "wss://remote.belabox.net/ws/remote";
+pub const BELABOX_WS: &str = "wss://remote.belabox.net/ws/remote";
 
 #[derive(Error, Debug)]
 pub enum BelaboxError {
     #[error("websocket error")]
-    Connect(#[source] tungstenite::Error),
+    Connect(#[source] Box<tungstenite::Error>),
     #[error("websocket send error")]
-    Send(#[source] tungstenite::Error),
+    Send(#[source] Box<tungstenite::Error>),
     #[error("disconnected from BELABOX Cloud")]
     Disconnected,
     #[error("auth failed")]
@@ -44,35 +52,76 @@ pub enum BelaboxError {
     AlreadyRestarting,
 }
 
+/// What the BELABOX connection receives, the messages from belaUI and what
+/// the connection measures itself.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Message(Message),
+    /// The round trip time to BELABOX Cloud, measured with websocket pings
+    Rtt(Duration),
+    /// A message this version doesn't know yet, e.g. from a newer belaUI
+    Unknown {
+        key: String,
+        value: serde_json::Value,
+    },
+}
+
 pub struct Belabox {
     pub run_handle: JoinHandle<()>,
-    pub message_tx: Weak<broadcast::Sender<Message>>,
+    pub message_tx: Weak<broadcast::Sender<Event>>,
     write: mpsc::UnboundedSender<InnerMessage>,
+    auth: requests::Remote,
+    dry_run: bool,
 }
 
 #[derive(Debug)]
 struct InnerMessage {
     pub respond: oneshot::Sender<Result<(), BelaboxError>>,
     pub message: String,
+    /// Wait for the reconnect when disconnected
+    pub queue: bool,
 }
 
 impl Belabox {
-    pub async fn connect(key: String) -> Result<Self, BelaboxError> {
+    pub async fn connect(key: String, ws_url: String) -> Result<Self, BelaboxError> {
+        Self::connect_with(key, ws_url, config::Reconnect::default()).await
+    }
+
+    /// Connects with custom delays between reconnects.
+    pub async fn connect_with(
+        key: String,
+        ws_url: String,
+        reconnect: config::Reconnect,
+    ) -> Result<Self, BelaboxError> {
         let (inner_tx, inner_rx) = mpsc::unbounded_channel();
         let (message_tx, _) = broadcast::channel(100);
         let message_tx = Arc::new(message_tx);
 
         let auth = requests::Remote::AuthKey { key, version: 6 };
-        let run_handle = tokio::spawn(run_loop(auth, message_tx.clone(), inner_rx));
+        let run_handle = tokio::spawn(run_loop(
+            auth.clone(),
+            ws_url,
+            reconnect,
+            message_tx.clone(),
+            inner_rx,
+        ));
 
         Ok(Self {
             run_handle,
             message_tx: Arc::downgrade(&message_tx),
             write: inner_tx,
+            auth,
+            dry_run: false,
         })
     }
 
-    pub fn message_stream(&self) -> Result<broadcast::Receiver<Message>, BelaboxError> {
+    /// Only logs the requests which change something on the BELABOX instead
+    /// of sending them, the state is still received.
+    pub fn dry_run(self, dry_run: bool) -> Self {
+        Self { dry_run, ..self }
+    }
+
+    pub fn message_stream(&self) -> Result<broadcast::Receiver<Event>, BelaboxError> {
         let tx = self
             .message_tx
             .upgrade()
@@ -83,10 +132,20 @@ impl Belabox {
 
     pub async fn send(&self, request: Request) -> Result<(), BelaboxError> {
         let message = serde_json::to_string(&request).unwrap();
+
+        if self.dry_run && !matches!(request, Request::Remote(_) | Request::Keepalive(_)) {
+            info!(message, "Dry run, not sending");
+            return Ok(());
+        }
+
+        // Reconnecting authenticates and keeps the connection alive anyway
+        let queue = !matches!(request, Request::Remote(_) | Request::Keepalive(_));
+
         let (tx, rx) = oneshot::channel();
         let inner = InnerMessage {
             respond: tx,
             message,
+            queue,
         };
 
         self.write.send(inner).unwrap();
@@ -120,6 +179,10 @@ impl Belabox {
         self.command(requests::Command::Poweroff).await
     }
 
+    pub async fn reset_ssh_password(&self) -> Result<(), BelaboxError> {
+        self.command(requests::Command::ResetSshPass).await
+    }
+
     pub async fn bitrate(&self, max_br: u32) -> Result<(), BelaboxError> {
         let request = Request::Bitrate(requests::Bitrate { max_br });
 
@@ -131,19 +194,47 @@ impl Belabox {
 
         self.send(request).await
     }
+
+    pub async fn wifi_disconnect(&self, uuid: String) -> Result<(), BelaboxError> {
+        let request = Request::Wifi(requests::Wifi::Disconnect(uuid));
+
+        self.send(request).await
+    }
+
+    pub async fn wifi_forget(&self, uuid: String) -> Result<(), BelaboxError> {
+        let request = Request::Wifi(requests::Wifi::Forget(uuid));
+
+        self.send(request).await
+    }
+
+    /// Requests the full state again, belaUI sends it after every
+    /// authentication. This also happens automatically after reconnecting.
+    pub async fn sync(&self) -> Result<(), BelaboxError> {
+        let request = Request::Remote(self.auth.clone());
+
+        self.send(request).await
+    }
 }
 
 async fn run_loop(
     auth: requests::Remote,
-    message_tx: Arc<broadcast::Sender<Message>>,
+    ws_url: String,
+    reconnect: config::Reconnect,
+    message_tx: Arc<broadcast::Sender<Event>>,
     inner_rx: mpsc::UnboundedReceiver<InnerMessage>,
 ) {
     // Spawn thread to handle inner requests
     let request_write = Arc::new(Mutex::new(None));
-    tokio::spawn(handle_requests(inner_rx, request_write.clone()));
+    let (connected_tx, connected_rx) = watch::channel(false);
+    tokio::spawn(handle_requests(
+        inner_rx,
+        request_write.clone(),
+        connected_rx,
+        reconnect.to_owned(),
+    ));
 
     loop {
-        let ws_stream = get_connection().await;
+        let ws_stream = get_connection(&ws_url, &reconnect).await;
         let (mut write, read) = ws_stream.split();
 
         // Authenticate
@@ -156,13 +247,21 @@ async fn run_loop(
         {
             *request_write.lock().await = Some(write);
         }
+        let _ = connected_tx.send(true);
 
         // Spawn thread to handle keepalive
         let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
-        tokio::spawn(keepalive(request_write.clone(), cancel_rx));
+        let ping_sent = Arc::new(Mutex::new(None));
+        tokio::spawn(keepalive(
+            request_write.clone(),
+            ping_sent.clone(),
+            cancel_rx,
+        ));
 
         // Handle messages
-        if let Err(BelaboxError::AuthFailed) = handle_messages(read, message_tx.clone()).await {
+        if let Err(BelaboxError::AuthFailed) =
+            handle_messages(read, message_tx.clone(), ping_sent).await
+        {
             break;
         };
 
@@ -172,34 +271,67 @@ async fn run_loop(
         {
             *request_write.lock().await = None;
         }
+        let _ = connected_tx.send(false);
     }
 }
 
-async fn get_connection() -> WebSocketStream<MaybeTlsStream<TcpStream>> {
-    let mut retry_grow = 1;
+async fn get_connection(
+    ws_url: &str,
+    reconnect: &config::Reconnect,
+) -> WebSocketStream<MaybeTlsStream<TcpStream>> {
+    let mut attempt = 0;
 
     loop {
         info!("Connecting");
 
-        if let Ok((ws_stream, _)) = tokio_tungstenite::connect_async(BELABOX_WS).await {
+        if let Ok((ws_stream, _)) = tokio_tungstenite::connect_async(ws_url).await {
             info!("Connected");
             break ws_stream;
         }
 
-        let wait = 1 << retry_grow;
+        let wait = reconnect_delay(reconnect, attempt, random_fraction());
         warn!("Unable to connect");
-        info!("trying to connect again in {} seconds", wait);
-        tokio::time::sleep(Duration::from_secs(wait)).await;
+        info!(
+            "trying to connect again in {:.1} seconds",
+            wait.as_secs_f64()
+        );
+        tokio::time::sleep(wait).await;
 
-        if retry_grow < 5 {
-            retry_grow += 1;
-        }
+        attempt = attempt.saturating_add(1);
     }
 }
 
-async fn keepalive(write: Arc<Mutex<Option<Writer>>>, mut cancel_rx: oneshot::Receiver<()>) {
+/// The delay before the reconnect attempt, doubling from the min delay up to
+/// the max. The fraction between 0 and 1 picks the jitter.
+fn reconnect_delay(reconnect: &config::Reconnect, attempt: u32, fraction: f64) -> Duration {
+    let delay = reconnect
+        .min_delay
+        .saturating_mul(1 << attempt.min(16))
+        .min(reconnect.max_delay) as f64;
+    let jitter = delay * reconnect.jitter * (fraction * 2.0 - 1.0);
+
+    Duration::from_secs_f64((delay + jitter).max(0.0))
+}
+
+/// A random number between 0 and 1, good enough for jitter.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Sends a keepalive and a ping to measure the round trip time every 5
+/// seconds, starting right away.
+async fn keepalive(
+    write: Arc<Mutex<Option<Writer>>>,
+    ping_sent: Arc<Mutex<Option<Instant>>>,
+    mut cancel_rx: oneshot::Receiver<()>,
+) {
+    let mut interval = time::interval(Duration::from_secs(5));
+
     loop {
-        time::sleep(Duration::from_secs(5)).await;
+        interval.tick().await;
 
         if cancel_rx.try_recv().is_ok() {
             debug!("keepalive cancel received");
@@ -218,6 +350,11 @@ async fn keepalive(write: Arc<Mutex<Option<Writer>>>, mut cancel_rx: oneshot::Re
             {
                 break;
             }
+
+            *ping_sent.lock().await = Some(Instant::now());
+            if w.send(TMessage::Ping(Vec::new())).await.is_err() {
+                break;
+            }
         }
     }
 
@@ -226,9 +363,18 @@ async fn keepalive(write: Arc<Mutex<Option<Writer>>>, mut cancel_rx: oneshot::Re
 
 async fn handle_messages(
     mut read: Reader,
-    message_tx: Arc<broadcast::Sender<Message>>,
+    message_tx: Arc<broadcast::Sender<Event>>,
+    ping_sent: Arc<Mutex<Option<Instant>>>,
 ) -> Result<(), BelaboxError> {
     while let Some(Ok(message)) = read.next().await {
+        if let TMessage::Pong(_) = &message {
+            if let Some(sent) = ping_sent.lock().await.take() {
+                handle_event(Event::Rtt(sent.elapsed()), &message_tx).await?;
+            }
+
+            continue;
+        }
+
         if let TMessage::Close(info) = &message {
             if let Some(CloseFrame { reason, .. }) = info {
                 info!(%reason, "connection closed with reason");
@@ -238,12 +384,7 @@ async fn handle_messages(
         }
 
         if let TMessage::Text(text) = &message {
-            if let Ok(m) = serde_json::from_str::<Message>(text) {
-                handle_message(m, &message_tx).await?;
-                continue;
-            }
-
-            let text: serde_json::Value = match serde_json::from_str(text) {
+            let text = match serde_json::from_str::<serde_json::Map<_, _>>(text) {
                 Ok(o) => o,
                 Err(e) => {
                     error!(?e, text, "failed to deserialize");
@@ -251,29 +392,11 @@ async fn handle_messages(
                 }
             };
 
-            let text = match text.as_object() {
-                Some(o) => o,
-                None => {
-                    error!(?text, "not an object");
-                    continue;
+            for (key, value) in text {
+                if let Some(event) = parse_message(key, value) {
+                    handle_event(event, &message_tx).await?;
                 }
             }
-            .to_owned();
-
-            for obj in text {
-                let v: Vec<_> = vec![obj.to_owned()];
-                let x: serde_json::Value = v.into_iter().collect();
-
-                let m: Message = match serde_json::from_value(x) {
-                    Ok(o) => o,
-                    Err(e) => {
-                        error!(?e, ?obj, "failed to deserialize");
-                        continue;
-                    }
-                };
-
-                handle_message(m, &message_tx).await?;
-            }
         }
     }
 
@@ -282,44 +405,311 @@ async fn handle_messages(
     Ok(())
 }
 
-async fn handle_message(
-    m: Message,
-    message_tx: &Arc<broadcast::Sender<Message>>,
+/// Parses a single message by its key, newer belaUI versions might send
+/// messages with keys this version doesn't know yet.
+fn parse_message(key: String, value: serde_json::Value) -> Option<Event> {
+    if !Message::KEYS.contains(&key.as_str()) {
+        debug!(key, "unknown message");
+        return Some(Event::Unknown { key, value });
+    }
+
+    let object = serde_json::Map::from_iter([(key, value)]);
+    match serde_json::from_value(object.into()) {
+        Ok(m) => Some(Event::Message(m)),
+        Err(e) => {
+            error!(?e, "failed to deserialize");
+            None
+        }
+    }
+}
+
+async fn handle_event(
+    event: Event,
+    message_tx: &Arc<broadcast::Sender<Event>>,
 ) -> Result<(), BelaboxError> {
-    if let Message::Remote(messages::Remote::RemoteAuth(remote)) = &m {
+    if let Event::Message(Message::Remote(messages::Remote::RemoteAuth(remote))) = &event {
         if !remote.auth_key {
             error!("Failed to authenticate");
             return Err(BelaboxError::AuthFailed);
         }
     }
 
-    trace!(?m, "Received message");
-    let _ = message_tx.send(m);
+    trace!(?event, "Received message");
+    let _ = message_tx.send(event);
 
     Ok(())
 }
 
-// TODO: Add retry or timeout?
+/// Sends the requests, a request which can't be sent is retried a few times
+/// so it isn't lost while reconnecting. While disconnected the requests can
+/// also wait for the reconnect, the requests after it are queued meanwhile.
 async fn handle_requests(
     mut inner_rx: mpsc::UnboundedReceiver<InnerMessage>,
     write: Arc<Mutex<Option<Writer>>>,
+    mut connected: watch::Receiver<bool>,
+    reconnect: config::Reconnect,
 ) {
+    let queue_timeout = Duration::from_secs(reconnect.queue_timeout);
+
     while let Some(request) = inner_rx.recv().await {
         trace!(?request.message, "sending");
 
-        let mut lock = write.lock().await;
-        if let Some(w) = lock.as_mut() {
-            let res = w
-                .send(TMessage::Text(request.message))
-                .await
-                .map_err(BelaboxError::Send);
-
-            request.respond.send(res).unwrap();
-        } else {
-            request
-                .respond
-                .send(Err(BelaboxError::Disconnected))
-                .unwrap();
+        let mut attempt = 0;
+        let mut queued = !request.queue || queue_timeout.is_zero();
+        let res = loop {
+            let res = match write.lock().await.as_mut() {
+                Some(w) => w
+                    .send(TMessage::Text(request.message.to_owned()))
+                    .await
+                    .map_err(|e| BelaboxError::Send(Box::new(e))),
+                None => Err(BelaboxError::Disconnected),
+            };
+
+            if matches!(res, Err(BelaboxError::Disconnected)) && !queued {
+                queued = true;
+                debug!("disconnected, waiting for the reconnect");
+                let _ = time::timeout(queue_timeout, connected.wait_for(|c| *c)).await;
+                continue;
+            }
+
+            if res.is_ok() || attempt >= reconnect.retries {
+                break res;
+            }
+
+            attempt += 1;
+            debug!(attempt, "failed to send request, retrying");
+            time::sleep(Duration::from_millis(reconnect.retry_delay)).await;
+        };
+
+        let _ = request.respond.send(res);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::MockServer;
+
+    #[tokio::test]
+    async fn authenticates_with_key() {
+        let mut server = MockServer::start(Vec::new()).await;
+        let _belabox = Belabox::connect("testkey".to_string(), server.url.to_owned())
+            .await
+            .unwrap();
+
+        let expected = r#"{"remote":{"auth/key":{"key":"testkey","version":6}}}"#;
+        assert_eq!(server.request().await, expected);
+    }
+
+    #[test]
+    fn unknown_message() {
+        let value = serde_json::json!({ "enabled": true });
+        let unknown = parse_message("new_feature".to_string(), value.clone());
+        assert_eq!(
+            unknown,
+            Some(Event::Unknown {
+                key: "new_feature".to_string(),
+                value
+            })
+        );
+
+        let malformed = serde_json::json!({ "max_br": "high" });
+        assert_eq!(parse_message("bitrate".to_string(), malformed), None);
+
+        let bitrate = serde_json::json!({ "max_br": 5000 });
+        assert!(matches!(
+            parse_message("bitrate".to_string(), bitrate),
+            Some(Event::Message(Message::Bitrate(_)))
+        ));
+    }
+
+    #[test]
+    fn known_message_keys() {
+        let messages = [
+            mock::ENCODER_ONLINE,
+            mock::CONFIG,
+            mock::NETIF,
+            mock::REVISIONS,
+            mock::SENSORS,
+            mock::STATUS,
+            mock::PIPELINES,
+            mock::RELAYS,
+        ];
+
+        for message in messages {
+            let message = serde_json::from_str::<Message>(message).unwrap();
+            let value = serde_json::to_value(message).unwrap();
+            let key = value.as_object().unwrap().keys().next().unwrap();
+            assert!(Message::KEYS.contains(&key.as_str()), "{key} isn't known");
         }
     }
+
+    #[tokio::test]
+    async fn forwards_unknown_messages() {
+        let server = MockServer::start(vec![r#"{"new_feature":{"enabled":true}}"#]).await;
+        let belabox = Belabox::connect("testkey".to_string(), server.url.to_owned())
+            .await
+            .unwrap();
+        let mut messages = belabox.message_stream().unwrap();
+
+        let (key, value) = loop {
+            if let Event::Unknown { key, value } = messages.recv().await.unwrap() {
+                break (key, value);
+            }
+        };
+
+        assert_eq!(key, "new_feature");
+        assert_eq!(value, serde_json::json!({ "enabled": true }));
+    }
+
+    #[tokio::test]
+    async fn measures_rtt() {
+        let server = MockServer::start(Vec::new()).await;
+        let belabox = Belabox::connect("testkey".to_string(), server.url.to_owned())
+            .await
+            .unwrap();
+        let mut messages = belabox.message_stream().unwrap();
+
+        let rtt = time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Event::Rtt(rtt) = messages.recv().await.unwrap() {
+                    break rtt;
+                }
+            }
+        })
+        .await
+        .expect("timed out waiting for the rtt");
+
+        assert!(rtt < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn reconnect_delay_capped() {
+        let reconnect = config::Reconnect {
+            min_delay: 2,
+            max_delay: 32,
+            jitter: 0.2,
+            ..Default::default()
+        };
+
+        let delays = (0..7)
+            .map(|attempt| reconnect_delay(&reconnect, attempt, 0.5).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [2, 4, 8, 16, 32, 32, 32]);
+
+        assert_eq!(reconnect_delay(&reconnect, 3, 0.0).as_secs_f64(), 12.8);
+        assert_eq!(reconnect_delay(&reconnect, 3, 1.0).as_secs_f64(), 19.2);
+        assert_eq!(reconnect_delay(&reconnect, 40, 0.5).as_secs(), 32);
+    }
+
+    #[tokio::test]
+    async fn retries_requests() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let reconnect = config::Reconnect {
+            retries: 2,
+            retry_delay: 10,
+            ..Default::default()
+        };
+        let (_connected_tx, connected_rx) = watch::channel(false);
+        tokio::spawn(handle_requests(
+            rx,
+            Default::default(),
+            connected_rx,
+            reconnect,
+        ));
+
+        let (respond, response) = oneshot::channel();
+        tx.send(InnerMessage {
+            respond,
+            message: "{}".to_string(),
+            queue: true,
+        })
+        .unwrap();
+
+        let start = Instant::now();
+        let res = response.await.unwrap();
+        assert!(matches!(res, Err(BelaboxError::Disconnected)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn queues_requests_while_disconnected() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let reconnect = config::Reconnect {
+            retries: 0,
+            queue_timeout: 1,
+            ..Default::default()
+        };
+        let (connected_tx, connected_rx) = watch::channel(false);
+        tokio::spawn(handle_requests(
+            rx,
+            Default::default(),
+            connected_rx,
+            reconnect,
+        ));
+
+        let send = |queue| {
+            let (respond, response) = oneshot::channel();
+            tx.send(InnerMessage {
+                respond,
+                message: "{}".to_string(),
+                queue,
+            })
+            .unwrap();
+            response
+        };
+
+        // Not queued
+        let start = Instant::now();
+        assert!(send(false).await.unwrap().is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Gives up after the timeout
+        assert!(send(true).await.unwrap().is_err());
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        // Stops waiting once reconnected
+        let start = Instant::now();
+        let response = send(true);
+        time::sleep(Duration::from_millis(10)).await;
+        connected_tx.send(true).unwrap();
+        assert!(response.await.unwrap().is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn dry_run() {
+        let mut server = MockServer::start(Vec::new()).await;
+        let belabox = Belabox::connect("testkey".to_string(), server.url.to_owned())
+            .await
+            .unwrap()
+            .dry_run(true);
+
+        // auth
+        server.request().await;
+
+        belabox.poweroff().await.unwrap();
+        belabox.sync().await.unwrap();
+
+        let expected = r#"{"remote":{"auth/key":{"key":"testkey","version":6}}}"#;
+        assert_eq!(server.request().await, expected);
+    }
+
+    #[tokio::test]
+    async fn replays_messages() {
+        let server = MockServer::start(vec![mock::CONFIG]).await;
+        let belabox = Belabox::connect("testkey".to_string(), server.url.to_owned())
+            .await
+            .unwrap();
+        let mut messages = belabox.message_stream().unwrap();
+
+        let config = loop {
+            if let Event::Message(Message::Config(config)) = messages.recv().await.unwrap() {
+                break config;
+            }
+        };
+
+        assert_eq!(config.max_br, 5000);
+        assert_eq!(config.asrc, "No audio");
+    }
 }
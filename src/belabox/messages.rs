@@ -20,6 +20,26 @@ pub enum Message {
     Relays(Relays),
 }
 
+impl Message {
+    /// The keys of the messages above, every other key is a message this
+    /// version doesn't know yet.
+    pub const KEYS: [&'static str; 13] = [
+        "config",
+        "remote",
+        "netif",
+        "revisions",
+        "sensors",
+        "status",
+        "updating",
+        "wifi",
+        "notification",
+        "bitrate",
+        "pipelines",
+        "acodecs",
+        "relays",
+    ];
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase", untagged)]
 pub enum Remote {
@@ -45,7 +65,11 @@ pub struct RemoteRevision {
     pub revision: String,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+/// The fields differ between belaUI versions, every field is defaulted so a
+/// config with missing fields is still received, see
+/// [`Config::missing_fields`]. Unknown fields are ignored.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
 pub struct Config {
     pub remote_key: String,
     pub max_br: u32,
@@ -60,6 +84,23 @@ pub struct Config {
     pub relay_account: String,
 }
 
+impl Config {
+    /// The fields which have to be set before the stream can be started.
+    pub fn missing_fields(&self) -> Vec<&'static str> {
+        [
+            ("pipeline", self.pipeline.is_empty()),
+            ("max_br", self.max_br == 0),
+            ("srt_latency", self.srt_latency == 0),
+            ("asrc", self.asrc.is_empty()),
+            ("acodec", self.acodec.is_empty()),
+        ]
+        .into_iter()
+        .filter(|(_, missing)| *missing)
+        .map(|(name, _)| name)
+        .collect()
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Netif {
     pub ip: String,
@@ -70,6 +111,13 @@ pub struct Netif {
     pub error: Option<String>,
 }
 
+impl Netif {
+    /// The current bitrate in kbps.
+    pub fn bitrate(&self) -> u64 {
+        (self.tp * 8) / 1024
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Pipeline {
     pub acodec: bool,
@@ -93,6 +141,11 @@ pub enum StatusKind {
     StreamingStatus(StreamingStatus),
     #[serde(rename = "wifi")]
     Wifi(WifiChange),
+    #[serde(rename = "ssh")]
+    Ssh(SshStatus),
+    #[serde(rename = "updating")]
+    Updating(UpdatingStatus),
+    /// Matches any status as the field is optional, so it has to be last
     #[serde(rename = "available_updates")]
     AvailableUpdates(AvailableUpdatesStatus),
 }
@@ -107,6 +160,17 @@ pub struct Status {
     pub asrcs: Vec<String>,
 }
 
+impl Status {
+    pub fn is_updating(&self) -> bool {
+        self.updating.as_ref().is_some_and(update_in_progress)
+    }
+}
+
+/// belaUI sends the progress while updating and the result once done.
+fn update_in_progress(updating: &serde_json::Value) -> bool {
+    !updating.is_null() && updating.get("result").is_none()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Updating {
     pub updating: Update,
@@ -125,6 +189,22 @@ pub struct WifiChange {
     pub wifi: HashMap<String, Wifi>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct UpdatingStatus {
+    pub updating: serde_json::Value,
+}
+
+impl UpdatingStatus {
+    pub fn is_updating(&self) -> bool {
+        update_in_progress(&self.updating)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SshStatus {
+    pub ssh: Ssh,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct AvailableUpdatesStatus {
     pub available_updates: Option<AvailableUpdates>,
@@ -170,6 +250,29 @@ pub struct Sensors {
     pub soc_temperature: String,
 }
 
+/// The sensors are strings like `62.4 C` or `5.12 V` for display, these are
+/// their numbers.
+impl Sensors {
+    /// The SoC voltage in V.
+    pub fn voltage(&self) -> Option<f64> {
+        self.soc_voltage.as_deref().and_then(sensor_number)
+    }
+
+    /// The SoC current in A.
+    pub fn current(&self) -> Option<f64> {
+        self.soc_current.as_deref().and_then(sensor_number)
+    }
+
+    /// The SoC temperature in °C.
+    pub fn temperature(&self) -> Option<f64> {
+        sensor_number(&self.soc_temperature)
+    }
+}
+
+fn sensor_number(value: &str) -> Option<f64> {
+    value.split_whitespace().next()?.parse().ok()
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Revisions {
     #[serde(rename = "belaUI")]
@@ -257,6 +360,33 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn config_missing_fields() {
+        let message = r#"{"config":{"remote_key":"key","max_br":5000,"srt_latency":2000,"pipeline":"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c","acodec":"opus"}}"#;
+
+        let Message::Config(config) = deserialize(message) else {
+            panic!("not a config");
+        };
+
+        assert_eq!(config.missing_fields(), vec!["asrc"]);
+    }
+
+    #[test]
+    fn config_other_version() {
+        // Without remote_key and relays, with fields this version doesn't know
+        let message = r#"{"config":{"password_hash":"$2b$10$abc","max_br":6000,"delay":-20,"pipeline":"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c","srt_latency":2000,"asrc":"USB audio","acodec":"opus","srtla_addr":"example.com","srtla_port":5000,"srt_streamid":"live"}}"#;
+
+        let Message::Config(config) = deserialize(message) else {
+            panic!("not a config");
+        };
+
+        assert_eq!(config.max_br, 6000);
+        assert_eq!(config.delay, -20);
+        assert_eq!(config.remote_key, "");
+        assert_eq!(config.ssh_pass, None);
+        assert!(config.missing_fields().is_empty());
+    }
+
     #[test]
     fn is_streaming() {
         let message = r#"{"status":{"is_streaming":true}}"#;
@@ -271,6 +401,59 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn sensor_numbers() {
+        let sensors = Sensors {
+            soc_voltage: Some("5.12 V".to_string()),
+            soc_current: None,
+            soc_temperature: "62.4 °C".to_string(),
+        };
+
+        assert_eq!(sensors.voltage(), Some(5.12));
+        assert_eq!(sensors.current(), None);
+        assert_eq!(sensors.temperature(), Some(62.4));
+
+        let sensors = Sensors {
+            soc_voltage: Some("unknown".to_string()),
+            ..sensors
+        };
+        assert_eq!(sensors.voltage(), None);
+    }
+
+    #[test]
+    fn updating_status() {
+        let Message::Status(StatusKind::Updating(progress)) = deserialize(
+            r#"{"status":{"updating":{"downloading":1,"unpacking":0,"setting_up":0,"total":5}}}"#,
+        ) else {
+            panic!("not an updating status");
+        };
+        assert!(progress.is_updating());
+
+        let Message::Status(StatusKind::Updating(done)) =
+            deserialize(r#"{"status":{"updating":{"result":0}}}"#)
+        else {
+            panic!("not an updating status");
+        };
+        assert!(!done.is_updating());
+    }
+
+    #[test]
+    fn ssh_status() {
+        let message = r#"{"status":{"ssh":{"user":"user","user_pass":true,"active":true}}}"#;
+
+        let parsed = deserialize(message);
+
+        let expected = Message::Status(StatusKind::Ssh(SshStatus {
+            ssh: Ssh {
+                user: "user".to_string(),
+                user_pass: true,
+                active: true,
+            },
+        }));
+
+        assert_eq!(parsed, expected);
+    }
+
     #[test]
     fn notification_show_empty() {
         let message = r#"{"notification":{"show":[]}}"#;
@@ -319,6 +502,20 @@ mod tests {
         assert_eq!(parsed, expected);
     }
 
+    #[test]
+    fn remote_revision() {
+        let message = r#"{"remote":{"revision":"a1b2c3d"}}"#;
+
+        let parsed = deserialize(message);
+        println!("{:#?}", parsed);
+
+        let expected = Message::Remote(Remote::RemoteRevision(RemoteRevision {
+            revision: "a1b2c3d".to_string(),
+        }));
+
+        assert_eq!(parsed, expected);
+    }
+
     fn deserialize(json: &str) -> Message {
         serde_json::from_str(json).unwrap()
     }
@@ -0,0 +1,106 @@
+//! A mock BELABOX Cloud server to test against.
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc,
+    time::{self, Duration},
+};
+use tokio_tungstenite::tungstenite::Message as TMessage;
+
+pub const AUTH: &str = r#"{"remote":{"auth/key":true}}"#;
+pub const ENCODER_ONLINE: &str = r#"{"remote":{"is_encoder_online":true,"version":6}}"#;
+pub const CONFIG: &str = r#"{"config":{"remote_key":"remote_key","max_br":5000,"delay":0,"pipeline":"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c","srt_latency":2000,"bitrate_overlay":false,"ssh_pass":"password","asrc":"No audio","acodec":"opus","relay_server":"1","relay_account":"1"}}"#;
+pub const NETIF: &str = r#"{"netif":{"eth0":{"ip":"192.168.1.10","txb":0,"tp":512000,"enabled":true},"usb0":{"ip":"192.168.42.2","txb":0,"tp":128000,"enabled":true}}}"#;
+pub const REVISIONS: &str = r#"{"revisions":{"belaUI":"d4e5f6a","belacoder":"b7c8d9e","srtla":"f0a1b2c","BELABOX image":"2024.03"}}"#;
+pub const SENSORS: &str = r#"{"sensors":{"SoC temperature":"52.1 °C"}}"#;
+pub const STATUS: &str = r#"{"status":{"is_streaming":false,"available_updates":null,"updating":null,"ssh":{"user":"user","user_pass":true,"active":false},"wifi":{"3":{"ifname":"wlan0","conn":"6a1f4b2e-3c5d-4e7f-8a9b-0c1d2e3f4a5b","available":[],"saved":{"Home":"6a1f4b2e-3c5d-4e7f-8a9b-0c1d2e3f4a5b"}}},"asrcs":["No audio","USB audio"]}}"#;
+pub const PIPELINES: &str = r#"{"pipelines":{"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c":{"acodec":true,"asrc":true,"name":"jetson/h265_camlink_1080p"},"0b3e4ec7a8e7d9b3d6e2d4b5d2a1c1e8f2e7a9c4":{"acodec":true,"asrc":true,"name":"jetson/h264_camlink_1080p"}}}"#;
+pub const RELAYS: &str = r#"{"relays":{"servers":{"1":{"name":"US East"},"2":{"name":"EU"},"3":{"name":"Asia"}},"accounts":{"1":{"name":"main"}}}}"#;
+pub const PIPELINE_H265: &str = "7ca3d9dd20726a7c2dad06948e1eadc6f84c461c";
+pub const PIPELINE_H264: &str = "0b3e4ec7a8e7d9b3d6e2d4b5d2a1c1e8f2e7a9c4";
+pub const SSH_PASS_RESET: &str = r#"{"config":{"remote_key":"remote_key","max_br":5000,"delay":0,"pipeline":"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c","srt_latency":2000,"bitrate_overlay":false,"ssh_pass":"new_password","asrc":"No audio","acodec":"opus","relay_server":"1","relay_account":"1"}}"#;
+pub const WIFI_UUID: &str = "6a1f4b2e-3c5d-4e7f-8a9b-0c1d2e3f4a5b";
+pub const WIFI_DISCONNECTED: &str = r#"{"status":{"wifi":{"3":{"ifname":"wlan0","conn":null,"available":[],"saved":{"Home":"6a1f4b2e-3c5d-4e7f-8a9b-0c1d2e3f4a5b"}}}}}"#;
+pub const WIFI_FORGOTTEN: &str =
+    r#"{"status":{"wifi":{"3":{"ifname":"wlan0","conn":null,"available":[],"saved":{}}}}}"#;
+pub const STREAMING: &str = r#"{"status":{"is_streaming":true}}"#;
+pub const NOT_STREAMING: &str = r#"{"status":{"is_streaming":false}}"#;
+
+pub struct MockServer {
+    pub url: String,
+    requests: mpsc::UnboundedReceiver<String>,
+}
+
+impl MockServer {
+    /// Starts a server which accepts any key and replays the given messages
+    /// to every connection after authenticating. Start, stop and WiFi
+    /// requests are answered with the new status.
+    pub async fn start(messages: Vec<&'static str>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (tx, requests) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                let tx = tx.clone();
+                let messages = messages.clone();
+
+                tokio::spawn(async move {
+                    // The first message is always the auth request
+                    if let Some(Ok(TMessage::Text(auth))) = ws.next().await {
+                        let _ = tx.send(auth);
+                    }
+
+                    ws.send(TMessage::Text(AUTH.to_string())).await.unwrap();
+
+                    for message in messages {
+                        ws.send(TMessage::Text(message.to_string())).await.unwrap();
+                    }
+
+                    while let Some(Ok(message)) = ws.next().await {
+                        if let TMessage::Text(text) = message {
+                            // Respond like the encoder would
+                            let status = if text.starts_with(r#"{"start""#) {
+                                Some(STREAMING)
+                            } else if text.starts_with(r#"{"stop""#) {
+                                Some(NOT_STREAMING)
+                            } else if text == r#"{"command":"reset_ssh_pass"}"# {
+                                Some(SSH_PASS_RESET)
+                            } else if text.starts_with(r#"{"wifi":{"disconnect""#) {
+                                Some(WIFI_DISCONNECTED)
+                            } else if text.starts_with(r#"{"wifi":{"forget""#) {
+                                Some(WIFI_FORGOTTEN)
+                            } else {
+                                None
+                            };
+
+                            let _ = tx.send(text);
+
+                            if let Some(status) = status {
+                                ws.send(TMessage::Text(status.to_string())).await.unwrap();
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        Self { url, requests }
+    }
+
+    /// Waits for the next request that isn't a keepalive.
+    pub async fn request(&mut self) -> String {
+        loop {
+            let request = time::timeout(Duration::from_secs(5), self.requests.recv())
+                .await
+                .expect("timed out waiting for a request")
+                .expect("server stopped");
+
+            if request != r#"{"keepalive":null}"# {
+                break request;
+            }
+        }
+    }
+}
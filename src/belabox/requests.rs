@@ -10,6 +10,7 @@ pub enum Request {
     Remote(Remote),
     Start(Start),
     Stop(u8),
+    Wifi(Wifi),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,6 +18,9 @@ pub enum Request {
 pub enum Command {
     Poweroff,
     Reboot,
+    /// Generates a new SSH password, the password is sent in the config
+    #[serde(rename = "reset_ssh_pass")]
+    ResetSshPass,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -59,6 +63,14 @@ impl From<super::messages::Config> for Start {
     }
 }
 
+/// WiFi connections are identified by their uuid from `Wifi.saved`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Wifi {
+    Disconnect(String),
+    Forget(String),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Bitrate {
     pub max_br: u32,
@@ -108,6 +120,16 @@ mod tests {
         assert_eq!(expected, json);
     }
 
+    #[test]
+    fn wifi_forget() {
+        let message = Request::Wifi(Wifi::Forget("uuid".to_string()));
+
+        let json = serde_json::to_string(&message).unwrap();
+
+        let expected = r#"{"wifi":{"forget":"uuid"}}"#;
+        assert_eq!(expected, json);
+    }
+
     #[test]
     fn stop() {
         let message = Request::Stop(0);
@@ -141,6 +163,17 @@ mod tests {
         assert_eq!(expected, json);
     }
 
+    #[test]
+    fn reset_ssh_pass() {
+        let message = Request::Command(Command::ResetSshPass);
+
+        let json = serde_json::to_string(&message).unwrap();
+        println!("{}", json);
+
+        let expected = r#"{"command":"reset_ssh_pass"}"#;
+        assert_eq!(expected, json);
+    }
+
     #[test]
     fn auth_key() {
         let message = Request::Remote(Remote::AuthKey {
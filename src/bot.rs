@@ -1,26 +1,43 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use tokio::{
-    sync::{broadcast::Receiver, Mutex, RwLock},
-    task::JoinHandle,
+    net::TcpListener,
+    sync::{
+        broadcast::{error::RecvError, Receiver},
+        Mutex, Notify, RwLock,
+    },
+    task::{AbortHandle, JoinHandle},
     time::{self, Instant},
 };
+use tracing::{error, info, warn};
 
 use crate::{
     belabox::{
         self,
         messages::{Remote, StatusKind},
     },
-    config::{self, BotCommand},
+    config,
+    discord::{Embed, EmbedField},
     error::Error,
+    http, monitor,
+    sink::{ActionSink, ChannelSink, FileSink},
     twitch::HandleMessage,
-    Belabox, CommandHandler, Monitor, Settings, Twitch,
+    Belabox, CommandHandler, Discord, MessageSink, Monitor, Settings, Twitch,
 };
 
 pub struct Bot {
     pub bb_msg_handle: JoinHandle<()>,
     pub bb_monitor_handle: JoinHandle<()>,
     pub tw_msg_handle: JoinHandle<()>,
+    pub discord_stats_handle: Option<JoinHandle<()>>,
+    pub http_handle: Option<JoinHandle<()>>,
+    pub heartbeat_handle: Option<JoinHandle<()>>,
     pub twitch: Arc<Twitch>,
     pub belabox: Arc<Belabox>,
 }
@@ -29,42 +46,300 @@ pub struct Bot {
 pub struct BelaState {
     pub online: bool,
     pub is_streaming: bool,
-    pub restart: bool,
+    /// Notified when `is_streaming` changes
+    pub streaming_changed: Arc<Notify>,
+    /// The number of messages skipped because a receiver lagged behind
+    pub dropped_messages: Arc<AtomicU64>,
+    /// The last measured round trip time to BELABOX Cloud
+    pub cloud_rtt: Option<time::Duration>,
+    /// The revision of the BELABOX Cloud remote
+    pub remote_revision: Option<String>,
+    /// When a reboot of the streaming BELABOX was requested, cleared once it's
+    /// back or the reboot timed out
+    pub restart: Option<Instant>,
+    /// The recent automatic restarts of the stream
+    pub auto_actions: monitor::LoopGuard,
+    /// The stream was stopped on purpose and shouldn't be restarted
+    pub stop_requested: bool,
+    /// When the last start was requested, used to report a failed start
+    pub start_requested: Option<Instant>,
     pub notify_ups: Option<bool>,
+    pub notify_temperature: Option<bool>,
+    /// Since when the measured bitrate has been below the target
+    pub below_target_since: Option<Instant>,
+    /// The measured bitrate was announced as too low for the target
+    pub notify_target: bool,
+    /// The number of available updates which was last announced
+    pub available_updates: Option<u32>,
     pub config: Option<belabox::messages::Config>,
+    /// The config the bot sent to belaUI, until belaUI reports it back its
+    /// changes aren't announced as changed externally
+    pub expected_config: Option<belabox::messages::Config>,
+    /// The settings belaUI had when the bot changed the config while not
+    /// streaming, belaUI only gets those changes with the next start
+    pub unsent_config_base: Option<config::Preset>,
     pub netif: Option<HashMap<String, belabox::messages::Netif>>,
+    /// When the interfaces were last received
+    pub last_netif_update: Option<Instant>,
+    /// When any message from BELABOX Cloud was last received
+    pub last_message: Option<Instant>,
+    /// The last bitrates in kbps of every interface, oldest first
+    pub bitrate_history: HashMap<String, VecDeque<u64>>,
     pub sensors: Option<belabox::messages::Sensors>,
     pub notification_timeout: HashMap<String, time::Instant>,
+    /// Seconds the same notification isn't announced again, can be changed
+    /// in chat
+    pub notification_window: u64,
+    /// The last notifications and when they were received, oldest first
+    pub notifications: VecDeque<(Instant, belabox::messages::NotificationMessage)>,
     pub network_timeout: time::Instant,
+    /// The monitor announcements in chat are muted until then
+    pub muted_until: Option<Instant>,
+    /// Post the stats while streaming, can be toggled with a command
+    pub auto_stats: bool,
+    /// Seconds between the automatic stats
+    pub auto_stats_interval: u64,
     pub pipelines: Option<HashMap<String, belabox::messages::Pipeline>>,
     pub asrcs: Option<Vec<String>>,
+    pub relays: Option<belabox::messages::Relays>,
+    /// The WiFi interfaces by their device id
+    pub wifi: Option<HashMap<String, belabox::messages::Wifi>>,
+    pub ssh: Option<belabox::messages::Ssh>,
+    /// belaUI is installing updates
+    pub updating: bool,
+    /// When the bot started
+    pub started: Instant,
+    /// The versions of the software on the BELABOX
+    pub revisions: Option<belabox::messages::Revisions>,
 }
 
 impl Default for BelaState {
     fn default() -> Self {
         Self {
             network_timeout: Instant::now(),
+            muted_until: Default::default(),
+            auto_stats: Default::default(),
+            auto_stats_interval: Default::default(),
             online: Default::default(),
             is_streaming: Default::default(),
+            streaming_changed: Default::default(),
+            dropped_messages: Default::default(),
+            cloud_rtt: Default::default(),
+            remote_revision: Default::default(),
             restart: Default::default(),
+            auto_actions: Default::default(),
+            stop_requested: Default::default(),
+            start_requested: Default::default(),
             notify_ups: Default::default(),
+            notify_temperature: Default::default(),
+            below_target_since: Default::default(),
+            notify_target: Default::default(),
+            available_updates: Default::default(),
             config: Default::default(),
+            expected_config: Default::default(),
+            unsent_config_base: Default::default(),
             netif: Default::default(),
+            last_netif_update: Default::default(),
+            last_message: Default::default(),
+            bitrate_history: Default::default(),
             sensors: Default::default(),
             notification_timeout: Default::default(),
+            notification_window: Default::default(),
+            notifications: Default::default(),
             pipelines: Default::default(),
             asrcs: Default::default(),
+            relays: Default::default(),
+            wifi: Default::default(),
+            ssh: Default::default(),
+            updating: Default::default(),
+            started: Instant::now(),
+            revisions: Default::default(),
+        }
+    }
+}
+
+/// The number of bitrates kept per interface.
+const BITRATE_HISTORY_LEN: usize = 60;
+
+/// The number of notifications kept.
+const NOTIFICATIONS_LEN: usize = 10;
+
+/// The interfaces are sent every second, after this long without an update
+/// the bitrates are no longer live.
+const NETIF_STALE_AFTER: time::Duration = time::Duration::from_secs(10);
+
+/// BELABOX Cloud answers the keepalive every 5 seconds, after this long
+/// without any message the connection is gone.
+const CLOUD_STALE_AFTER: time::Duration = time::Duration::from_secs(30);
+
+impl BelaState {
+    /// The summed bitrate of all enabled interfaces in kbps.
+    pub fn total_bitrate(&self) -> u64 {
+        self.netif
+            .iter()
+            .flat_map(|netifs| netifs.values())
+            .filter(|i| i.enabled)
+            .map(|i| i.bitrate())
+            .sum()
+    }
+
+    /// Stores the config reported by belaUI while keeping the changes of the
+    /// bot which belaUI doesn't know about yet.
+    pub fn receive_config(&mut self, mut config: belabox::messages::Config) {
+        if let (Some(base), Some(current)) = (self.unsent_config_base.take(), &self.config) {
+            let received = config::Preset::from(&config);
+            config::Preset::from(current).apply_changes(&base, &mut config);
+            self.unsent_config_base =
+                (config::Preset::from(&config) != received).then_some(received);
+        }
+
+        self.config = Some(config);
+    }
+
+    /// The monitor announcements in chat are muted, an elapsed mute is
+    /// cleared.
+    pub fn is_muted(&mut self) -> bool {
+        if self.muted_until.is_some_and(|m| m <= Instant::now()) {
+            self.muted_until = None;
+        }
+
+        self.muted_until.is_some()
+    }
+
+    /// The last received interfaces are too old to be trusted, e.g. during a
+    /// BELABOX Cloud outage.
+    pub fn is_netif_stale(&self) -> bool {
+        self.last_netif_update
+            .is_some_and(|l| l.elapsed() > NETIF_STALE_AFTER)
+    }
+
+    /// No message was received from BELABOX Cloud recently.
+    pub fn is_cloud_stale(&self) -> bool {
+        self.last_message
+            .is_none_or(|l| l.elapsed() > CLOUD_STALE_AFTER)
+    }
+
+    /// Keeps the notification, persistent notifications are sent again
+    /// while they're shown so a repeat of the last one is skipped.
+    pub fn add_notification(&mut self, notification: belabox::messages::NotificationMessage) {
+        let repeated = self.notifications.back().is_some_and(|(_, last)| {
+            last.name == notification.name && last.msg == notification.msg
+        });
+
+        if repeated {
+            return;
+        }
+
+        if self.notifications.len() == NOTIFICATIONS_LEN {
+            self.notifications.pop_front();
+        }
+
+        self.notifications.push_back((Instant::now(), notification));
+    }
+
+    /// Adds the current bitrates to the history, interfaces which are gone
+    /// are removed.
+    pub fn update_bitrate_history(&mut self, netif: &HashMap<String, belabox::messages::Netif>) {
+        self.bitrate_history
+            .retain(|name, _| netif.contains_key(name));
+
+        for (name, i) in netif {
+            let history = self.bitrate_history.entry(name.to_owned()).or_default();
+
+            if history.len() == BITRATE_HISTORY_LEN {
+                history.pop_front();
+            }
+
+            history.push_back(if i.enabled { i.bitrate() } else { 0 });
         }
     }
 }
 
 impl Bot {
     pub async fn new(config: Settings) -> Result<Self, Error> {
-        let twitch = Arc::new(Twitch::run(config.twitch.clone()).await?);
-        let belabox = Arc::new(Belabox::connect(config.belabox.remote_key.to_owned()).await?);
+        let twitch = Arc::new(
+            Twitch::run(
+                config.twitch.clone(),
+                &config.reconnect,
+                config.path.clone(),
+            )
+            .await?,
+        );
+
+        let monitor_channel = match config.twitch.monitor_channel.as_str() {
+            "" => twitch.channel().to_owned(),
+            channel => channel.to_owned(),
+        };
+        let monitor_sink: Arc<dyn MessageSink> = if config.twitch.action_announcements {
+            Arc::new(ActionSink {
+                twitch: twitch.clone(),
+                channel: monitor_channel,
+            })
+        } else if config.twitch.monitor_channel.is_empty() {
+            twitch.clone()
+        } else {
+            Arc::new(ChannelSink {
+                twitch: twitch.clone(),
+                channel: monitor_channel,
+            })
+        };
+
+        let sink: Arc<dyn MessageSink> = if config.twitch.action_replies {
+            Arc::new(ActionSink {
+                twitch: twitch.clone(),
+                channel: twitch.channel().to_owned(),
+            })
+        } else {
+            twitch.clone()
+        };
+
+        Self::start(config, twitch, sink, monitor_sink).await
+    }
+
+    /// Creates the bot with a custom sink for all the messages it sends,
+    /// commands are still read from Twitch chat.
+    pub async fn with_sink(config: Settings, sink: Arc<dyn MessageSink>) -> Result<Self, Error> {
+        let twitch = Arc::new(
+            Twitch::run(
+                config.twitch.clone(),
+                &config.reconnect,
+                config.path.clone(),
+            )
+            .await?,
+        );
+
+        Self::start(config, twitch, sink.clone(), sink).await
+    }
+
+    /// The announcements are sent to the monitor sink, command replies to
+    /// the sink.
+    async fn start(
+        config: Settings,
+        twitch: Arc<Twitch>,
+        sink: Arc<dyn MessageSink>,
+        monitor_sink: Arc<dyn MessageSink>,
+    ) -> Result<Self, Error> {
+        let belabox = Arc::new(
+            Belabox::connect_with(
+                config.belabox.remote_key.to_owned(),
+                config.belabox.ws_url.to_owned(),
+                config.reconnect.to_owned(),
+            )
+            .await?
+            .dry_run(config.dry_run),
+        );
+
+        if config.dry_run {
+            warn!("Dry run enabled, nothing will be sent to the BELABOX");
+        }
 
         // Create state to store BELABOX information
-        let bela_state = Arc::new(RwLock::new(BelaState::default()));
+        let bela_state = Arc::new(RwLock::new(BelaState {
+            auto_stats: config.belabox.monitor.network,
+            auto_stats_interval: config.belabox.monitor.network_timeout,
+            notification_window: config.belabox.monitor.notification_timeout,
+            ..Default::default()
+        }));
 
         // Access to the command handler
         let command_handler = Arc::new(Mutex::new(None));
@@ -73,63 +348,234 @@ impl Bot {
         let bb_msg_handle = tokio::spawn(handle_belabox_messages(
             belabox.message_stream()?,
             belabox.clone(),
-            twitch.clone(),
+            monitor_sink.clone(),
             bela_state.clone(),
         ));
 
+        // Post stats to Discord
+        let discord_stats_handle = (!config.discord.stats_webhook.is_empty()).then(|| {
+            tokio::spawn(handle_discord_stats(
+                Discord::new(config.discord.stats_webhook.to_owned()),
+                config.discord.stats_interval,
+                config.discord.stats_block,
+                bela_state.clone(),
+                command_handler.clone(),
+            ))
+        });
+
+        // The TestAlert command sends a message to all of these
+        let mut external_sinks: Vec<(&'static str, Arc<dyn MessageSink>)> = Vec::new();
+        if !config.discord.webhook.is_empty() {
+            let discord = Discord::new(config.discord.webhook.to_owned());
+            external_sinks.push(("Discord alerts", Arc::new(discord)));
+        }
+        if !config.discord.stats_webhook.is_empty() {
+            let discord = Discord::new(config.discord.stats_webhook.to_owned());
+            external_sinks.push(("Discord stats", Arc::new(discord)));
+        }
+
         let bb_monitor_handle = tokio::spawn(handle_belabox_monitor(
             belabox.message_stream()?,
-            belabox.clone(),
-            twitch.clone(),
+            monitor_sink,
             config.belabox.monitor,
             bela_state.clone(),
             command_handler.clone(),
             config.belabox.custom_interface_name.clone(),
+            config.discord,
         ));
 
+        let config::CommandLog {
+            file,
+            discord_webhook,
+            twitch_channel,
+        } = config.command_log;
+        let mut command_log: Vec<(&'static str, Arc<dyn MessageSink>)> = Vec::new();
+        if !file.is_empty() {
+            command_log.push(("Command log file", Arc::new(FileSink { path: file })));
+        }
+        if !discord_webhook.is_empty() {
+            let discord = Discord::new(discord_webhook);
+            command_log.push(("Command log Discord", Arc::new(discord)));
+        }
+        if !twitch_channel.is_empty() {
+            twitch.join(&twitch_channel)?;
+            let channel = ChannelSink {
+                twitch: twitch.clone(),
+                channel: twitch_channel,
+            };
+            command_log.push(("Command log Twitch", Arc::new(channel)));
+        }
+        external_sinks.extend(command_log.iter().cloned());
+
+        let handler = CommandHandler {
+            sink,
+            belabox: belabox.clone(),
+            bela_state: bela_state.clone(),
+            commands: Arc::new(RwLock::new(config.commands)),
+            custom_interface_name: config.belabox.custom_interface_name,
+            pipeline_aliases: config.belabox.pipeline_aliases,
+            asrc_aliases: config.belabox.asrc_aliases,
+            number_interfaces: config.belabox.number_interfaces,
+            stats_temperature: config.belabox.stats_temperature,
+            stats_target: config.belabox.stats_target,
+            monitor_stats_format: config.belabox.monitor_stats_format,
+            defaults: config.belabox.defaults,
+            restart_delay: config.belabox.restart_delay,
+            match_threshold: config.belabox.match_threshold,
+            reboot_timeout: config.belabox.reboot_timeout,
+            operation: Default::default(),
+            admins: config.twitch.admins,
+            grants: Default::default(),
+            grant_duration: config.twitch.grant_duration,
+            offline_message: config.belabox.offline_message,
+            offline_cooldown: config.belabox.offline_cooldown,
+            last_offline_reply: Default::default(),
+            last_used: Default::default(),
+            presets: Arc::new(RwLock::new(config.presets)),
+            command_log: command_log.into_iter().map(|(_, sink)| sink).collect(),
+            external_sinks,
+            max_reply_length: config.twitch.max_reply_length,
+            split_replies: config.twitch.split_replies,
+            config_path: Some(config.path),
+        };
+
         // Read Twitch messages
         let tw_msg_handle = tokio::spawn(handle_twitch_messages(
             twitch.message_stream()?,
-            belabox.clone(),
-            twitch.clone(),
-            config.commands,
-            config.belabox.custom_interface_name,
-            config.twitch.admins,
-            bela_state,
-            command_handler,
+            handler,
+            command_handler.clone(),
         ));
 
+        // The bot is only healthy while these are running
+        let tasks = Arc::new(vec![
+            bb_msg_handle.abort_handle(),
+            tw_msg_handle.abort_handle(),
+            belabox.run_handle.abort_handle(),
+            twitch.read_handle.abort_handle(),
+        ]);
+
+        // Serve the HTTP endpoints
+        let http_handle = if config.http.address.is_empty() {
+            None
+        } else {
+            let listener = TcpListener::bind(&config.http.address)
+                .await
+                .map_err(Error::Http)?;
+            info!(address = config.http.address, "HTTP server listening");
+
+            let bela_state = bela_state.clone();
+            let tasks = tasks.clone();
+            let token = config.http.token.to_owned();
+            Some(tokio::spawn(async move {
+                if let Err(e) =
+                    http::serve(listener, bela_state, tasks, command_handler, token).await
+                {
+                    error!(?e, "HTTP server stopped");
+                }
+            }))
+        };
+
+        // Log that the bot is still alive
+        let heartbeat_handle = (config.heartbeat != 0)
+            .then(|| tokio::spawn(heartbeat(config.heartbeat, bela_state.clone(), tasks)));
+
         Ok(Self {
             bb_msg_handle,
             bb_monitor_handle,
             tw_msg_handle,
+            discord_stats_handle,
+            http_handle,
+            heartbeat_handle,
             twitch,
             belabox,
         })
     }
+
+    /// Runs until one of the handlers stops, there is no way to recover when
+    /// that happens.
+    pub async fn run(&mut self) {
+        tokio::select! {
+            _ = &mut self.bb_msg_handle => {}
+            _ = &mut self.tw_msg_handle => {}
+        };
+    }
+
+    /// Stops all running tasks.
+    pub fn shutdown(&self) {
+        self.bb_msg_handle.abort();
+        self.bb_monitor_handle.abort();
+        self.tw_msg_handle.abort();
+        if let Some(handle) = &self.discord_stats_handle {
+            handle.abort();
+        }
+        if let Some(handle) = &self.http_handle {
+            handle.abort();
+        }
+        if let Some(handle) = &self.heartbeat_handle {
+            handle.abort();
+        }
+        self.belabox.run_handle.abort();
+        self.twitch.read_handle.abort();
+    }
 }
 
-async fn handle_belabox_messages(
-    mut bb_msg: Receiver<belabox::Message>,
+/// Receives the next message, when the receiver fell behind the dropped
+/// messages are skipped and counted instead of stopping. `None` once the
+/// sender is gone.
+pub(crate) async fn recv<T: Clone>(receiver: &mut Receiver<T>, dropped: &AtomicU64) -> Option<T> {
+    loop {
+        match receiver.recv().await {
+            Ok(message) => return Some(message),
+            Err(RecvError::Lagged(skipped)) => {
+                warn!(skipped, "Receiver lagged behind, skipped messages");
+                dropped.fetch_add(skipped, Ordering::Relaxed);
+            }
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+pub(crate) async fn handle_belabox_messages(
+    mut bb_msg: Receiver<belabox::Event>,
     belabox: Arc<Belabox>,
-    twitch: Arc<Twitch>,
+    sink: Arc<dyn MessageSink>,
     bela_state: Arc<RwLock<BelaState>>,
 ) {
-    use belabox::Message;
+    use belabox::{Event, Message};
+
+    let dropped = bela_state.read().await.dropped_messages.clone();
+
+    while let Some(event) = recv(&mut bb_msg, &dropped).await {
+        bela_state.write().await.last_message = Some(Instant::now());
+
+        let message = match event {
+            Event::Message(message) => message,
+            Event::Rtt(rtt) => {
+                bela_state.write().await.cloud_rtt = Some(rtt);
+                continue;
+            }
+            Event::Unknown { .. } => continue,
+        };
 
-    while let Ok(message) = bb_msg.recv().await {
         match message {
             Message::Config(config) => {
                 let mut lock = bela_state.write().await;
-                lock.config = Some(config);
+                lock.receive_config(config);
             }
             Message::Remote(Remote::RemoteEncoder(remote)) => {
                 let mut lock = bela_state.write().await;
                 lock.online = remote.is_encoder_online
             }
+            Message::Remote(Remote::RemoteRevision(remote)) => {
+                info!(revision = remote.revision, "BELABOX Cloud remote revision");
+                let mut lock = bela_state.write().await;
+                lock.remote_revision = Some(remote.revision);
+            }
             Message::Netif(netif) => {
                 let mut lock = bela_state.write().await;
+                lock.update_bitrate_history(&netif);
                 lock.netif = Some(netif);
+                lock.last_netif_update = Some(Instant::now());
             }
             Message::Sensors(sensors) => {
                 let mut lock = bela_state.write().await;
@@ -143,11 +589,21 @@ async fn handle_belabox_messages(
             }
             Message::Status(status) => {
                 let mut lock = bela_state.write().await;
+                let was_streaming = lock.is_streaming;
 
                 match status {
                     StatusKind::Status(s) => {
+                        lock.updating = s.is_updating();
                         lock.is_streaming = s.is_streaming;
                         lock.asrcs = Some(s.asrcs);
+                        lock.wifi = Some(s.wifi);
+                        lock.ssh = Some(s.ssh);
+                    }
+                    StatusKind::Ssh(s) => {
+                        lock.ssh = Some(s.ssh);
+                    }
+                    StatusKind::Updating(u) => {
+                        lock.updating = u.is_updating();
                     }
                     StatusKind::Asrcs(a) => {
                         lock.asrcs = Some(a.asrcs);
@@ -155,69 +611,256 @@ async fn handle_belabox_messages(
                     StatusKind::StreamingStatus(ss) => {
                         lock.is_streaming = ss.is_streaming;
                     }
-                    StatusKind::Wifi(_) => {}
+                    StatusKind::Wifi(w) => {
+                        lock.wifi = Some(w.wifi);
+                    }
                     StatusKind::AvailableUpdates(_) => {}
                 };
 
-                if lock.restart {
-                    lock.restart = false;
+                if lock.is_streaming != was_streaming {
+                    lock.streaming_changed.notify_waiters();
+                }
 
-                    if let Some(config) = &lock.config {
-                        let request = belabox::requests::Start::from(config.to_owned());
+                if lock.restart.take().is_some() {
+                    if let Some(config) = lock.config.to_owned() {
+                        lock.expected_config = Some(config.to_owned());
+                        lock.unsent_config_base = None;
+                        let request = belabox::requests::Start::from(config);
                         let _ = belabox.start(request).await;
 
                         let msg = "BB: Reboot successful, starting the stream".to_string();
-                        let _ = twitch.send(msg).await;
+                        let _ = sink.send(msg).await;
                     }
                 }
             }
+            Message::Wifi(w) => {
+                let mut lock = bela_state.write().await;
+                lock.wifi = Some(w.wifi);
+            }
             Message::Pipelines(pipelines) => {
                 let mut lock = bela_state.write().await;
                 lock.pipelines = Some(pipelines);
             }
+            Message::Notification(belabox::messages::Notifications::Show(show)) => {
+                let mut lock = bela_state.write().await;
+                for notification in show.show {
+                    lock.add_notification(notification);
+                }
+            }
+            Message::Relays(relays) => {
+                let mut lock = bela_state.write().await;
+                lock.relays = Some(relays);
+            }
+            Message::Revisions(revisions) => {
+                let mut lock = bela_state.write().await;
+                lock.revisions = Some(revisions);
+            }
+            Message::Updating(_) => {
+                let mut lock = bela_state.write().await;
+                lock.updating = true;
+            }
             _ => {}
         }
     }
 }
 
 async fn handle_belabox_monitor(
-    bb_msg: Receiver<belabox::Message>,
-    belabox: Arc<Belabox>,
-    twitch: Arc<Twitch>,
+    bb_msg: Receiver<belabox::Event>,
+    sink: Arc<dyn MessageSink>,
     monitor: config::Monitor,
     bela_state: Arc<RwLock<BelaState>>,
     command_handler: Arc<Mutex<Option<CommandHandler>>>,
     custom_interface_name: HashMap<String, String>,
+    discord: config::Discord,
 ) {
+    let config::Discord {
+        webhook, alerts, ..
+    } = discord;
+    let discord = (!webhook.is_empty()).then(|| Discord::new(webhook));
+
     let handler = Monitor {
-        belabox,
         bela_state,
-        twitch,
+        sink,
+        discord,
+        alerts,
         command_handler,
         custom_interface_name,
+        messages: monitor.messages.to_owned(),
     };
     handler.run(bb_msg, monitor).await;
 }
 
-#[allow(clippy::too_many_arguments)]
+/// Logs every interval whether the tasks are running and BELABOX Cloud is
+/// still sending messages.
+async fn heartbeat(
+    interval: u64,
+    bela_state: Arc<RwLock<BelaState>>,
+    tasks: Arc<Vec<AbortHandle>>,
+) {
+    let mut interval = time::interval(time::Duration::from_secs(interval));
+
+    loop {
+        interval.tick().await;
+
+        let tasks_running = !tasks.iter().any(AbortHandle::is_finished);
+        let cloud_stale = bela_state.read().await.is_cloud_stale();
+
+        if tasks_running && !cloud_stale {
+            info!("Heartbeat");
+        } else {
+            warn!(tasks_running, cloud_stale, "Heartbeat, unhealthy");
+        }
+    }
+}
+
+async fn handle_discord_stats(
+    discord: Discord,
+    interval: u64,
+    block: bool,
+    bela_state: Arc<RwLock<BelaState>>,
+    command_handler: Arc<Mutex<Option<CommandHandler>>>,
+) {
+    let mut interval = time::interval(time::Duration::from_secs(interval.max(1)));
+
+    loop {
+        interval.tick().await;
+
+        let (is_streaming, sensors) = {
+            let lock = bela_state.read().await;
+            (lock.is_streaming, lock.sensors.to_owned())
+        };
+
+        if !is_streaming {
+            continue;
+        }
+
+        let stats = {
+            let lock = command_handler.lock().await;
+            let Some(ch) = &*lock else { continue };
+            let stats = if block {
+                ch.stats_block().await
+            } else {
+                ch.stats().await
+            };
+            let Ok(stats) = stats else {
+                continue;
+            };
+            stats
+        };
+
+        let mut embed = Embed {
+            title: "BELABOX".to_string(),
+            description: stats.reply,
+            ..Default::default()
+        };
+
+        if let Some(sensors) = sensors {
+            embed.fields.push(EmbedField {
+                name: "Temperature".to_string(),
+                value: sensors.soc_temperature,
+                inline: true,
+            });
+        }
+
+        if let Err(e) = discord.send_embed(embed).await {
+            error!(?e, "error sending stats to discord");
+        }
+    }
+}
+
 async fn handle_twitch_messages(
     tw_msg: Receiver<HandleMessage>,
-    belabox: Arc<Belabox>,
-    twitch: Arc<Twitch>,
-    commands: HashMap<BotCommand, config::CommandInformation>,
-    custom_interface_name: HashMap<String, String>,
-    admins: Vec<String>,
-    bela_state: Arc<RwLock<BelaState>>,
+    handler: CommandHandler,
     command_handler: Arc<Mutex<Option<CommandHandler>>>,
 ) {
-    let handler = CommandHandler {
-        twitch,
-        belabox,
-        bela_state,
-        commands,
-        custom_interface_name,
-        admins,
-    };
     *command_handler.lock().await = Some(handler.clone());
     handler.run(tw_msg).await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn recv_skips_lagged() {
+        let (tx, mut rx) = tokio::sync::broadcast::channel(2);
+        for i in 0..4 {
+            tx.send(i).unwrap();
+        }
+
+        let dropped = AtomicU64::new(0);
+        assert_eq!(recv(&mut rx, &dropped).await, Some(2));
+        assert_eq!(recv(&mut rx, &dropped).await, Some(3));
+        assert_eq!(dropped.load(Ordering::Relaxed), 2);
+
+        drop(tx);
+        assert_eq!(recv(&mut rx, &dropped).await, None);
+    }
+
+    fn netif(tp: u64) -> belabox::messages::Netif {
+        belabox::messages::Netif {
+            ip: "192.168.1.10".to_string(),
+            txb: None,
+            tp,
+            enabled: true,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn bitrate_history() {
+        let mut state = BelaState::default();
+
+        for tp in 0..=BITRATE_HISTORY_LEN as u64 {
+            let netifs = HashMap::from([("eth0".to_string(), netif(tp * 128))]);
+            state.update_bitrate_history(&netifs);
+        }
+
+        let history = &state.bitrate_history["eth0"];
+        assert_eq!(history.len(), BITRATE_HISTORY_LEN);
+        assert_eq!(history.front(), Some(&1));
+        assert_eq!(history.back(), Some(&(BITRATE_HISTORY_LEN as u64)));
+
+        let netifs = HashMap::from([("usb0".to_string(), netif(128))]);
+        state.update_bitrate_history(&netifs);
+
+        assert!(!state.bitrate_history.contains_key("eth0"));
+        assert_eq!(state.bitrate_history["usb0"], [1]);
+    }
+
+    #[test]
+    fn receive_config_keeps_unsent_changes() {
+        let received: belabox::messages::Config = serde_json::from_str(r#"{"remote_key":"remote_key","max_br":5000,"delay":0,"pipeline":"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c","srt_latency":2000,"bitrate_overlay":false,"ssh_pass":null,"asrc":"No audio","acodec":"opus","relay_server":"1","relay_account":"1"}"#)
+            .unwrap();
+
+        let mut state = BelaState::default();
+        state.receive_config(received.clone());
+
+        // The bot changed the latency while not streaming
+        state.unsent_config_base = Some(config::Preset::from(&received));
+        state.config.as_mut().unwrap().srt_latency = 500;
+
+        // Someone else changed the bitrate in belaUI
+        let external = belabox::messages::Config {
+            max_br: 3000,
+            ..received.clone()
+        };
+        state.receive_config(external.clone());
+
+        let config = state.config.as_ref().unwrap();
+        assert_eq!(config.srt_latency, 500);
+        assert_eq!(config.max_br, 3000);
+        assert_eq!(
+            state.unsent_config_base,
+            Some(config::Preset::from(&external))
+        );
+
+        // belaUI got the changes with the start
+        let started = belabox::messages::Config {
+            srt_latency: 500,
+            ..external
+        };
+        state.receive_config(started);
+        assert!(state.unsent_config_base.is_none());
+    }
+}
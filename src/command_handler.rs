@@ -1,352 +1,1399 @@
 use std::fmt::Write as _;
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::{atomic::AtomicU64, Arc},
+};
 
-use tokio::sync::{broadcast, RwLock};
-use tracing::{debug, error, info};
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tracing::{debug, error, info, warn};
 
 use crate::{
     belabox::{self, BelaboxError},
     bot::BelaState,
-    config::{self, BotCommand, Permission},
+    config::{self, BotCommand, Permission, StatsPart},
     error::{Error, Result},
-    twitch, Belabox, Twitch,
+    Belabox, MessageSink,
 };
 
+/// A chat message which might contain a command, implemented for every chat
+/// platform commands can be read from.
+pub trait CommandMessage: std::fmt::Debug + Clone + Send + Sync + 'static {
+    fn channel(&self) -> &str;
+    fn sender_id(&self) -> &str;
+    fn sender_name(&self) -> &str;
+    fn is_broadcaster(&self) -> bool;
+    fn is_moderator(&self) -> bool;
+    fn is_vip(&self) -> bool;
+
+    fn is_subscriber(&self) -> bool {
+        false
+    }
+    fn text(&self) -> &str;
+
+    /// The id of the message to reply to, if the platform supports replies.
+    fn message_id(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// How a command went, so integrations can tell failures apart from
+/// informational replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The command did what was asked or only shows information
+    Info,
+    /// The command wasn't carried out, e.g. because of an invalid argument
+    Warning,
+    /// The command failed
+    Error,
+}
+
+/// The result of a command, rendered to text as the chat reply.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutcome {
+    pub reply: String,
+    pub severity: Severity,
+    /// Long lists are split into multiple messages at this delimiter instead
+    /// of being truncated
+    pub split_on: Option<&'static str>,
+}
+
+impl CommandOutcome {
+    pub fn info(reply: impl Into<String>) -> Self {
+        Self {
+            reply: reply.into(),
+            severity: Severity::Info,
+            split_on: None,
+        }
+    }
+
+    pub fn warning(reply: impl Into<String>) -> Self {
+        Self {
+            reply: reply.into(),
+            severity: Severity::Warning,
+            split_on: None,
+        }
+    }
+
+    pub fn error(reply: impl Into<String>) -> Self {
+        Self {
+            reply: reply.into(),
+            severity: Severity::Error,
+            split_on: None,
+        }
+    }
+
+    /// Marks the reply as a list of items separated by the delimiter.
+    pub fn list(mut self, delimiter: &'static str) -> Self {
+        self.split_on = Some(delimiter);
+        self
+    }
+}
+
+impl std::fmt::Display for CommandOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.reply)
+    }
+}
+
+impl PartialEq<&str> for CommandOutcome {
+    fn eq(&self, other: &&str) -> bool {
+        self.reply == *other
+    }
+}
+
 #[derive(Clone)]
 pub struct CommandHandler {
-    pub twitch: Arc<Twitch>,
+    pub sink: Arc<dyn MessageSink>,
     pub belabox: Arc<Belabox>,
     pub bela_state: Arc<RwLock<BelaState>>,
-    pub commands: HashMap<config::BotCommand, config::CommandInformation>,
+    /// Shared so permissions can be changed in chat
+    pub commands: Arc<RwLock<HashMap<config::BotCommand, config::CommandInformation>>>,
     pub custom_interface_name: HashMap<String, String>,
+    /// Pipeline ids selected by an exact name instead of the closest match
+    pub pipeline_aliases: HashMap<String, String>,
+    /// Audio sources selected by an exact name instead of the closest match
+    pub asrc_aliases: HashMap<String, String>,
+    pub number_interfaces: bool,
+    pub stats_temperature: bool,
+    pub stats_target: bool,
+    /// What the automatic stats contain
+    pub monitor_stats_format: Vec<config::StatsPart>,
     pub admins: Vec<String>,
+    /// Users with temporary moderator permissions and when it expires
+    pub grants: Arc<RwLock<HashMap<String, tokio::time::Instant>>>,
+    /// Default minutes a grant lasts
+    pub grant_duration: u64,
+    pub offline_message: String,
+    /// Min seconds between offline replies
+    pub offline_cooldown: u64,
+    pub last_offline_reply: Arc<Mutex<Option<tokio::time::Instant>>>,
+    /// When each command with a cooldown was last used
+    pub last_used: Arc<Mutex<HashMap<BotCommand, tokio::time::Instant>>>,
+    pub presets: Arc<RwLock<HashMap<String, config::Preset>>>,
+    /// Where the used commands and their results are logged
+    pub command_log: Vec<Arc<dyn MessageSink>>,
+    /// The Discord webhooks and log destinations by name, to test them
+    pub external_sinks: Vec<(&'static str, Arc<dyn MessageSink>)>,
+    /// Longer replies are truncated or split, 0 to send them as is
+    pub max_reply_length: usize,
+    /// Split longer replies into multiple messages instead of truncating them
+    pub split_replies: bool,
+    /// The config file changes made in chat are saved to, not saved when
+    /// `None`
+    pub config_path: Option<std::path::PathBuf>,
+    pub defaults: config::Defaults,
+    /// Max seconds to wait for the stream to stop before starting it again
+    pub restart_delay: u64,
+    /// Min similarity of a pipeline or audio source name to the query
+    pub match_threshold: f64,
+    /// Max seconds to wait for the BELABOX to come back after a reboot
+    pub reboot_timeout: u64,
+    /// Held while restarting the stream so restarts don't overlap
+    pub operation: Arc<Mutex<()>>,
 }
 
 impl CommandHandler {
-    pub async fn run(&self, mut messages: broadcast::Receiver<twitch::HandleMessage>) {
-        while let Ok(hm) = messages.recv().await {
+    pub async fn run<M>(&self, mut messages: broadcast::Receiver<M>)
+    where
+        M: CommandMessage,
+    {
+        let dropped = self.bela_state.read().await.dropped_messages.clone();
+
+        while let Some(hm) = crate::bot::recv(&mut messages, &dropped).await {
             debug!("Handle message: {:?}", hm);
 
-            let mut split_message = hm.message.split_whitespace();
+            let mut split_message = hm.text().split_whitespace();
+
+            let Some(command) = split_message.next() else {
+                continue;
+            };
 
-            // You can't send a blank message.. hopefully
-            let command = split_message.next().unwrap().to_lowercase();
-            let (command, info) = match self.command(command) {
+            let (command, info) = match self.command(command).await {
                 Some(c) => c,
                 None => continue,
             };
             debug!(?command, "found command");
 
-            if !self.is_allowed_to_execute(&info.permission, &hm) {
+            let Some(permission) = info.permission_in(hm.channel()).cloned() else {
+                continue;
+            };
+
+            if !self.is_allowed_to_execute(&permission, &hm).await {
                 continue;
             };
 
-            info!("{} used command {:?}", hm.sender_name, command);
+            info!("{} used command {:?}", hm.sender_name(), command);
+
+            let offline = !{ self.bela_state.read().await.online };
+            if offline && info.requires_online(&command) {
+                if self.offline_reply_allowed().await {
+                    self.reply(&hm, self.offline_message.to_owned()).await;
+                }
+
+                continue;
+            }
+
+            if let Some(remaining) = self.cooldown_remaining(&command, &info).await {
+                if info.cooldown_reply {
+                    let message = format!("{} on cooldown ({}s)", info.command, remaining);
+                    self.reply(&hm, message).await;
+                }
 
-            if !{ self.bela_state.read().await.online } {
-                self.send("Offline :(".to_string()).await;
                 continue;
             }
 
-            let response = match command {
-                BotCommand::AudioDelay => self.audio_delay(split_message.next()).await,
-                BotCommand::AudioSrc => self.audio_src(split_message).await,
-                BotCommand::Bitrate => self.bitrate(split_message.next()).await,
-                BotCommand::Latency => self.latency(split_message.next()).await,
-                BotCommand::Network => self.network(split_message.next()).await,
-                BotCommand::Pipeline => self.pipeline(split_message).await,
-                BotCommand::Poweroff => self.poweroff().await,
-                BotCommand::Restart => self.restart().await,
-                BotCommand::Sensor => self.sensor().await,
-                BotCommand::Start => self.start().await,
-                BotCommand::Stats => self.stats().await,
-                BotCommand::Stop => self.stop().await,
+            let outcome = self.execute(command.clone(), split_message).await;
+
+            // Never post the SSH password in chat
+            let whisper = info.whisper || command == BotCommand::SshPassword;
+            let logged = if whisper {
+                "(whispered)"
+            } else {
+                &outcome.reply
             };
+            self.log_command(&hm, logged).await;
+
+            if whisper {
+                self.whisper(&hm, outcome.reply).await;
+            } else {
+                let parts = self.reply_parts(outcome.reply, outcome.split_on);
+                self.send_multi(hm.message_id(), parts).await;
+            }
+        }
+    }
+
+    /// Logs who used which command and the result for accountability.
+    async fn log_command(&self, hm: &impl CommandMessage, result: &str) {
+        let message = format!("{} used {}: {}", hm.sender_name(), hm.text(), result);
 
-            match response {
-                Ok(message) => self.send(message).await,
-                Err(e) => self.send(format!("Error {}", e)).await,
+        for sink in &self.command_log {
+            if let Err(e) = sink.send(message.to_owned()).await {
+                warn!(?e, "error logging the command");
             }
         }
     }
 
     async fn send(&self, message: String) {
-        if let Err(e) = self.twitch.send(message).await {
-            error!(?e, "error sending message to twitch");
+        self.send_multi(None, self.reply_parts(message, None)).await;
+    }
+
+    async fn reply(&self, hm: &impl CommandMessage, message: String) {
+        let parts = self.reply_parts(message, None);
+        self.send_multi(hm.message_id(), parts).await;
+    }
+
+    /// Sends the messages one after another with a pause in between, as a
+    /// reply to the message id when given.
+    async fn send_multi(&self, message_id: Option<&str>, parts: Vec<String>) {
+        for (i, part) in parts.into_iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(SPLIT_REPLY_DELAY).await;
+            }
+
+            let result = match message_id {
+                Some(id) => self.sink.reply(id.to_owned(), part).await,
+                None => self.sink.send(part).await,
+            };
+
+            if let Err(e) = result {
+                error!(?e, "error sending message");
+            }
+        }
+    }
+
+    /// Applies a change to the config file the bot was started with.
+    fn save_config<F>(&self, f: F) -> Result<(), config::ConfigError>
+    where
+        F: FnOnce(&mut config::Settings),
+    {
+        match &self.config_path {
+            Some(path) => config::Settings::update_file(path, f),
+            None => Ok(()),
         }
     }
 
-    fn command(
+    /// Fits the message in the max reply length, Twitch rejects longer ones.
+    /// Lists are always split at their delimiter instead of truncated.
+    fn reply_parts(&self, message: String, split_on: Option<&str>) -> Vec<String> {
+        let max = self.max_reply_length;
+        if max == 0 || message.chars().count() <= max {
+            return vec![message];
+        }
+
+        match (split_on, self.split_replies) {
+            (Some(delimiter), _) => split_reply(&message, max, delimiter),
+            (None, true) => split_reply(&message, max, " "),
+            (None, false) => vec![truncate_reply(&message, max)],
+        }
+    }
+
+    async fn whisper(&self, hm: &impl CommandMessage, message: String) {
+        if let Err(e) = self.sink.whisper(hm.sender_id(), message).await {
+            error!(?e, "error sending whisper");
+        }
+    }
+
+    /// Finds the command for the first word of a message, only a whole word
+    /// matches so `!bbs` doesn't match `!bbsensor`.
+    async fn command(
         &self,
-        command: String,
-    ) -> Option<(&config::BotCommand, &config::CommandInformation)> {
+        command: &str,
+    ) -> Option<(config::BotCommand, config::CommandInformation)> {
+        let command = command.to_lowercase();
+
         self.commands
+            .read()
+            .await
             .iter()
-            .find(|(_, info)| command == info.command)
+            .find(|(_, info)| command == info.command.to_lowercase())
+            .map(|(command, info)| (command.to_owned(), info.to_owned()))
     }
 
-    fn is_allowed_to_execute(
+    async fn is_allowed_to_execute(
         &self,
         permission: &config::Permission,
-        handle_message: &twitch::HandleMessage,
+        handle_message: &impl CommandMessage,
     ) -> bool {
-        let twitch::HandleMessage {
-            sender_name,
-            broadcaster,
-            moderator,
-            vip,
-            ..
-        } = handle_message;
-
-        let broadcaster = *broadcaster || self.admins.contains(sender_name);
-        let moderator = broadcaster || *moderator;
-        let vip = moderator || *vip;
+        let sender_name = handle_message.sender_name();
+
+        let broadcaster =
+            handle_message.is_broadcaster() || self.admins.iter().any(|a| a == sender_name);
+        let moderator =
+            broadcaster || handle_message.is_moderator() || self.is_granted(sender_name).await;
+        let vip = moderator || handle_message.is_vip();
+        let subscriber = vip || handle_message.is_subscriber();
 
         match permission {
             Permission::Broadcaster => broadcaster,
             Permission::Moderator => moderator,
             Permission::Vip => vip,
+            Permission::Subscriber => subscriber,
             Permission::Public => true,
         }
     }
 
-    pub async fn start(&self) -> Result<String> {
-        let (config, is_streaming) = {
+    /// Whether the command is refused while the BELABOX is offline.
+    pub async fn requires_online(&self, command: &BotCommand) -> bool {
+        match self.commands.read().await.get(command) {
+            Some(info) => info.requires_online(command),
+            None => !command.works_offline(),
+        }
+    }
+
+    /// Runs the command with its arguments, the permission has already
+    /// been checked.
+    pub async fn execute(
+        &self,
+        command: BotCommand,
+        split_message: std::str::SplitWhitespace<'_>,
+    ) -> CommandOutcome {
+        match self.dispatch(command, split_message).await {
+            Ok(outcome) => outcome,
+            Err(e) => CommandOutcome::error(format!("Error {}", e)),
+        }
+    }
+
+    async fn dispatch(
+        &self,
+        command: BotCommand,
+        mut split_message: std::str::SplitWhitespace<'_>,
+    ) -> Result<CommandOutcome> {
+        // Interrupting an update could leave the BELABOX unable to boot
+        if matches!(
+            command,
+            BotCommand::Start | BotCommand::Restart | BotCommand::Poweroff
+        ) && self.bela_state.read().await.updating
+            && split_message.next() != Some("force")
+        {
+            return Ok(CommandOutcome::warning("BB: System updating, please wait"));
+        }
+
+        match command {
+            BotCommand::AudioDelay => self.audio_delay(split_message.next()).await,
+            BotCommand::AudioCodec => self.audio_codec(split_message).await,
+            BotCommand::AudioSrc => self.audio_src(split_message).await,
+            BotCommand::AutoStats => self.auto_stats(split_message.next()).await,
+            BotCommand::Bitrate => self.bitrate(split_message.next()).await,
+            BotCommand::Bot => self.bot().await,
+            BotCommand::Cancel => self.cancel().await,
+            BotCommand::Grant => self.grant(split_message.next(), split_message.next()).await,
+            BotCommand::Defaults => self.defaults().await,
+            BotCommand::Info => self.info().await,
+            BotCommand::Interfaces => self.interfaces().await,
+            BotCommand::Latency => self.latency(split_message.next()).await,
+            BotCommand::Network => self.network(split_message.next()).await,
+            BotCommand::Notifications => self.notifications().await,
+            BotCommand::NotificationTimeout => {
+                self.notification_timeout(split_message.next()).await
+            }
+            BotCommand::Overlay => self.overlay().await,
+            BotCommand::Permission => self.permission(split_message).await,
+            BotCommand::Ping => self.ping().await,
+            BotCommand::Pipeline => self.pipeline(split_message).await,
+            BotCommand::PipelineInfo => self.pipeline_info(split_message).await,
+            BotCommand::Poweroff => self.poweroff().await,
+            BotCommand::Preset => self.preset(split_message).await,
+            BotCommand::Quiet => self.quiet(split_message.next()).await,
+            BotCommand::Relay => self.relay(split_message).await,
+            BotCommand::Restart => self.restart().await,
+            BotCommand::Sensor => self.sensor().await,
+            BotCommand::SshPassword => self.ssh_password().await,
+            BotCommand::Start => self.start().await,
+            BotCommand::Stats => self.stats().await,
+            BotCommand::Status => self.status().await,
+            BotCommand::Stop => self.stop().await,
+            BotCommand::Sync => self.sync().await,
+            BotCommand::TestAlert => self.test_alert().await,
+            BotCommand::WifiDisconnect => self.wifi_disconnect().await,
+            BotCommand::WifiForget => self.wifi_forget(split_message).await,
+        }
+    }
+
+    /// The offline reply is sent at most once per cooldown, an empty message
+    /// disables it.
+    async fn offline_reply_allowed(&self) -> bool {
+        if self.offline_message.is_empty() {
+            return false;
+        }
+
+        let mut last = self.last_offline_reply.lock().await;
+        let cooldown = tokio::time::Duration::from_secs(self.offline_cooldown);
+
+        if last.is_some_and(|l| l.elapsed() < cooldown) {
+            return false;
+        }
+
+        *last = Some(tokio::time::Instant::now());
+        true
+    }
+
+    /// The seconds left until the command can be used again, rounded up.
+    /// Returns `None` and marks the command as used when it's not on cooldown.
+    async fn cooldown_remaining(
+        &self,
+        command: &BotCommand,
+        info: &config::CommandInformation,
+    ) -> Option<u64> {
+        if info.cooldown == 0 {
+            return None;
+        }
+
+        let mut last_used = self.last_used.lock().await;
+        let cooldown = tokio::time::Duration::from_secs(info.cooldown);
+
+        if let Some(remaining) = last_used
+            .get(command)
+            .and_then(|l| cooldown.checked_sub(l.elapsed()))
+            .filter(|r| !r.is_zero())
+        {
+            let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+            return Some(secs);
+        }
+
+        last_used.insert(command.to_owned(), tokio::time::Instant::now());
+        None
+    }
+
+    /// Users with an unexpired grant have moderator permissions.
+    async fn is_granted(&self, user: &str) -> bool {
+        let user = user.to_lowercase();
+
+        self.grants
+            .read()
+            .await
+            .get(&user)
+            .is_some_and(|expiry| *expiry > tokio::time::Instant::now())
+    }
+
+    pub async fn start(&self) -> Result<CommandOutcome> {
+        let (config, is_streaming, asrcs) = {
             let read = self.bela_state.read().await;
-            (read.config.clone(), read.is_streaming)
+            (read.config.clone(), read.is_streaming, read.asrcs.clone())
         };
 
         let config = match config {
             Some(c) => c,
             None => {
-                return Ok("Error starting BELABOX".to_string());
+                return Ok(CommandOutcome::error(
+                    "Error the BELABOX config hasn't been received yet, wait for the BELABOX to connect"
+                        .to_string(),
+                ));
             }
         };
 
+        let missing = config.missing_fields();
+        if !missing.is_empty() {
+            return Ok(CommandOutcome::error(format!(
+                "Error the BELABOX config is missing: {}, set it in belaUI first",
+                missing.join(", ")
+            )));
+        }
+
         if is_streaming {
-            return Ok("Error already streaming".to_string());
+            return Ok(CommandOutcome::info("Already streaming"));
+        }
+
+        // belaUI doesn't start with an unplugged audio source
+        if let Some(asrcs) = asrcs {
+            if !asrcs.contains(&config.asrc) {
+                return Ok(CommandOutcome::error(format!(
+                    "Error the audio source {} is not available, change it first",
+                    config.asrc
+                )));
+            }
+        }
+
+        // Set before sending so belaUI can't report it back first
+        {
+            let mut lock = self.bela_state.write().await;
+            lock.expected_config = Some(config.to_owned());
+            lock.unsent_config_base = None;
         }
 
         let request = belabox::requests::Start::from(config);
         self.belabox.start(request).await?;
 
-        Ok("Starting BELABOX".to_string())
+        {
+            let mut lock = self.bela_state.write().await;
+            lock.stop_requested = false;
+            lock.start_requested = Some(tokio::time::Instant::now());
+        }
+
+        Ok(CommandOutcome::info("Starting BELABOX"))
     }
 
-    pub async fn stop(&self) -> Result<String> {
-        if !{ self.bela_state.read().await.is_streaming } {
-            return Ok("Error not streaming".to_string());
+    pub async fn stop(&self) -> Result<CommandOutcome> {
+        {
+            let mut lock = self.bela_state.write().await;
+            if !lock.is_streaming {
+                return Ok(CommandOutcome::info("Already stopped"));
+            }
+
+            lock.stop_requested = true;
         }
 
         self.belabox.stop().await?;
-        Ok("Stopping BELABOX".to_string())
+        Ok(CommandOutcome::info("Stopping BELABOX"))
+    }
+
+    /// Applies the change to the config, when streaming the stream is
+    /// restarted so the change takes effect. The reply of the start is
+    /// returned when it was refused, e.g. because of a missing audio source.
+    async fn update_config<F>(&self, f: F) -> Result<Option<CommandOutcome>>
+    where
+        F: FnOnce(&mut belabox::messages::Config),
+    {
+        let _operation = self.operation.lock().await;
+        let is_streaming = { self.bela_state.read().await.is_streaming };
+        let timeout = tokio::time::Duration::from_secs(self.restart_delay);
+
+        if is_streaming {
+            let _ = self.stop().await?;
+            self.send("Restarting the stream".to_string()).await;
+
+            if tokio::time::timeout(timeout, self.wait_for_streaming(false))
+                .await
+                .is_err()
+            {
+                warn!("timed out waiting for the stream to stop");
+            }
+        }
+
+        {
+            let mut lock = self.bela_state.write().await;
+            let state = &mut *lock;
+            if let Some(config) = state.config.as_mut() {
+                // belaUI only gets the new config with the next start
+                if !is_streaming && state.unsent_config_base.is_none() {
+                    state.unsent_config_base = Some(config::Preset::from(&*config));
+                }
+
+                f(config);
+            }
+        }
+
+        if is_streaming {
+            // The stop timed out, the change wasn't applied to the stream
+            if self.bela_state.read().await.is_streaming {
+                return Ok(Some(CommandOutcome::warning("Already streaming")));
+            }
+
+            let outcome = self.start().await?;
+            if outcome.severity != Severity::Info {
+                return Ok(Some(outcome));
+            }
+
+            // Keep the next restart from reading the state before the start
+            // has been confirmed
+            let _ = tokio::time::timeout(timeout, self.wait_for_streaming(true)).await;
+        }
+
+        Ok(None)
+    }
+
+    /// Waits until the BELABOX reports the given streaming state.
+    async fn wait_for_streaming(&self, is_streaming: bool) {
+        let notify = { self.bela_state.read().await.streaming_changed.clone() };
+
+        loop {
+            // Register before checking the state so a change in between
+            // isn't missed
+            let changed = notify.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+
+            if self.bela_state.read().await.is_streaming == is_streaming {
+                return;
+            }
+
+            changed.await;
+        }
     }
 
-    pub async fn stats(&self) -> Result<String> {
-        let (netifs, ups) = {
+    /// Lists the interfaces with their IP and which custom name they resolve
+    /// to, to find out why a custom name doesn't match.
+    pub async fn interfaces(&self) -> Result<CommandOutcome> {
+        let netifs = { self.bela_state.read().await.netif.to_owned() };
+        let netifs = netifs.unwrap_or_default();
+
+        if netifs.is_empty() {
+            return Ok(CommandOutcome::warning("No interfaces"));
+        }
+
+        let interfaces = sorted_interfaces(&netifs)
+            .into_iter()
+            .map(|(name, i)| {
+                // The custom name based on the IP wins, like in the stats
+                let custom = self
+                    .custom_interface_name
+                    .get(&i.ip)
+                    .map(|c| (c, "IP"))
+                    .or_else(|| self.custom_interface_name.get(name).map(|c| (c, "name")));
+
+                match custom {
+                    Some((custom, by)) => format!("{} ({}) = {} by {}", name, i.ip, custom, by),
+                    None => format!("{} ({}) has no custom name", name, i.ip),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Ok(CommandOutcome::info(interfaces.join(", ")).list(", "))
+    }
+
+    pub async fn stats(&self) -> Result<CommandOutcome> {
+        let stats = self.stats_joined(", ", None).await;
+        Ok(CommandOutcome::info(stats).list(", "))
+    }
+
+    /// The stats posted automatically, only contains the configured parts.
+    pub async fn monitor_stats(&self) -> String {
+        let parts = &self.monitor_stats_format;
+        let parts = (!parts.is_empty()).then_some(parts.as_slice());
+
+        self.stats_joined(", ", parts).await
+    }
+
+    /// The stats with a line for every interface and the total, for places
+    /// which aren't limited to a single line like Discord.
+    pub async fn stats_block(&self) -> Result<CommandOutcome> {
+        Ok(CommandOutcome::info(self.stats_joined("\n", None).await))
+    }
+
+    /// Without parts given the total is only added for multiple interfaces
+    /// and the temperature when enabled in the config.
+    async fn stats_joined(&self, separator: &str, parts: Option<&[StatsPart]>) -> String {
+        let (netifs, stale, ups, total_bitrate, sensors, max_br) = {
             let read = self.bela_state.read().await;
-            (read.netif.to_owned(), read.notify_ups)
+            (
+                read.netif.to_owned(),
+                read.is_netif_stale(),
+                read.notify_ups,
+                read.total_bitrate(),
+                read.sensors.to_owned(),
+                read.config.as_ref().map(|c| c.max_br),
+            )
         };
 
-        let mut total_bitrate = 0;
-        let mut interfaces = netifs
-            .iter()
-            .flatten()
-            .map(|(mut name, i)| {
+        let netifs = netifs.unwrap_or_default();
+
+        let interfaces = sorted_interfaces(&netifs)
+            .into_iter()
+            .enumerate()
+            .map(|(index, (name, i))| {
                 let value = if i.enabled {
-                    let bitrate = (i.tp * 8) / 1024;
-                    total_bitrate += bitrate;
-                    format!("{} kbps", bitrate)
+                    format!("{} kbps", i.bitrate())
                 } else {
                     "disabled".to_string()
                 };
 
-                // Check if custom interface name based on interface
-                if let Some(custom) = self.custom_interface_name.get(name) {
-                    name = custom;
-                }
+                let name = interface_name(name, i, &self.custom_interface_name);
 
-                // Check if custom interface name based on IP
-                if let Some(custom) = self.custom_interface_name.get(&i.ip) {
-                    name = custom;
+                if self.number_interfaces {
+                    format!("{}) {}: {}", index + 1, name, value)
+                } else {
+                    format!("{}: {}", name, value)
                 }
-
-                format!("{}: {}", name, value)
             })
             .collect::<Vec<String>>();
 
-        // Sort interfaces because they like to move around
-        interfaces.sort();
+        let include = |part, default| parts.map_or(default, |p| p.contains(&part));
+        let show_total = include(StatsPart::Total, interfaces.len() > 1);
+        let show_ups = include(StatsPart::Ups, true);
+        let show_temperature = include(StatsPart::Temperature, self.stats_temperature);
 
-        let mut msg = interfaces.join(", ");
+        let mut parts = if include(StatsPart::Interfaces, true) {
+            interfaces
+        } else {
+            Vec::new()
+        };
+
+        if show_total {
+            let mut total = format!("Total: {total_bitrate} kbps");
+
+            if let (true, Some(max_br)) = (self.stats_target, max_br) {
+                let _ = write!(total, " (target {max_br})");
+            }
 
-        if interfaces.len() > 1 {
-            msg = format!("{msg}, Total: {total_bitrate} kbps");
+            parts.push(total);
         }
 
-        if let Some(connected) = ups {
+        if let (true, Some(connected)) = (show_ups, ups) {
             let a = if !connected { "not" } else { "" };
-            let _ = write!(msg, ", UPS: {} charging", a);
+            parts.push(format!("UPS: {} charging", a));
         }
 
-        Ok(msg)
+        if let (true, Some(sensors)) = (show_temperature, sensors) {
+            parts.push(format!("Temp: {}", sensors.soc_temperature));
+        }
+
+        let msg = parts.join(separator);
+
+        if stale {
+            return format!("(stale) {msg}");
+        }
+
+        msg
     }
 
-    pub async fn restart(&self) -> Result<String> {
-        let is_streaming = {
+    pub async fn restart(&self) -> Result<CommandOutcome> {
+        let requested = {
             let mut lock = self.bela_state.write().await;
 
-            if lock.restart {
+            if lock.restart.is_some() {
                 return Err(Error::Belabox(BelaboxError::AlreadyRestarting));
             }
 
             if lock.is_streaming {
-                lock.restart = true;
+                lock.restart = Some(tokio::time::Instant::now());
             }
 
-            lock.is_streaming
+            lock.restart
         };
 
-        if is_streaming {
+        if let Some(requested) = requested {
             self.belabox.stop().await?;
+
+            let handler = self.clone();
+            tokio::spawn(async move { handler.reboot_timed_out(requested).await });
         }
 
         self.belabox.restart().await?;
-        Ok("Rebooting BELABOX".to_string())
+        Ok(CommandOutcome::info("Rebooting BELABOX"))
     }
 
-    pub async fn poweroff(&self) -> Result<String> {
-        self.belabox.poweroff().await?;
-        Ok("Powering off BELABOX".to_string())
+    /// Cancels the restart of the stream after a reboot, the BELABOX still
+    /// reboots but the stream stays stopped.
+    pub async fn cancel(&self) -> Result<CommandOutcome> {
+        if self.bela_state.write().await.restart.take().is_none() {
+            return Ok(CommandOutcome::warning("No restart in progress"));
+        }
+
+        Ok(CommandOutcome::info(
+            "Restart cancelled; stream will not auto-start",
+        ))
     }
 
-    pub async fn bitrate(&self, bitrate: Option<&str>) -> Result<String> {
-        let bitrate = match bitrate {
-            Some(b) => b,
-            None => {
-                return Ok("No bitrate given".to_string());
-            }
-        };
+    /// Clears the restart when the BELABOX didn't come back in time, otherwise
+    /// every restart after a failed reboot would be refused.
+    async fn reboot_timed_out(&self, requested: tokio::time::Instant) {
+        tokio::time::sleep(tokio::time::Duration::from_secs(self.reboot_timeout)).await;
 
-        let bitrate = match bitrate.parse::<u32>() {
-            Ok(b) => b,
-            Err(_) => {
-                return Ok(format!("Invalid number {} given", bitrate));
+        {
+            let mut lock = self.bela_state.write().await;
+            if lock.restart != Some(requested) {
+                return;
             }
-        };
 
-        if !(500..=12000).contains(&bitrate) {
-            let msg = format!(
-                "Invalid value: {}, use a value between 500 - 12000",
-                bitrate
-            );
-            return Ok(msg);
+            lock.restart = None;
         }
 
-        let bitrate = increment_by_step(bitrate as f64, 250.0) as u32;
-        self.belabox.bitrate(bitrate).await?;
-
-        {
-            let mut read = self.bela_state.write().await;
-            if let Some(config) = &mut read.config {
-                config.max_br = bitrate;
-            }
-        }
+        warn!("reboot timed out");
+        self.send(format!(
+            "BB: Reboot appears to have failed, the BELABOX didn't come back within {} seconds",
+            self.reboot_timeout
+        ))
+        .await;
+    }
 
-        Ok(format!("Changed max bitrate to {} kbps", bitrate))
+    pub async fn poweroff(&self) -> Result<CommandOutcome> {
+        self.belabox.poweroff().await?;
+        Ok(CommandOutcome::info("Powering off BELABOX"))
     }
 
-    pub async fn network(&self, name: Option<&str>) -> Result<String> {
-        let name = match name {
-            Some(b) => b.to_lowercase(),
-            None => {
-                return Ok("No interface given".to_string());
-            }
+    pub async fn grant(&self, user: Option<&str>, minutes: Option<&str>) -> Result<CommandOutcome> {
+        let Some(user) = user else {
+            return Ok(CommandOutcome::warning("No user given"));
         };
 
-        let netifs = {
-            let read = self.bela_state.read().await;
-            read.netif.to_owned()
-        };
+        let user = user.trim_start_matches('@').to_lowercase();
 
-        let netifs = match netifs {
-            Some(n) => n,
-            None => {
-                return Ok("Interfaces not available".to_string());
-            }
+        let minutes = match minutes.map(|m| m.parse::<u64>()) {
+            Some(Ok(m)) => m,
+            Some(Err(_)) => return Ok(CommandOutcome::warning("Invalid number of minutes given")),
+            None => self.grant_duration,
         };
 
-        if netifs.len() == 1 {
-            return Ok("You only have one connection!".to_string());
-        }
+        let mut grants = self.grants.write().await;
 
-        let disabled_count = {
-            let mut total = 0;
+        // Clean up expired grants while we're here
+        let now = tokio::time::Instant::now();
+        grants.retain(|_, expiry| *expiry > now);
 
-            for v in netifs.values() {
-                if !v.enabled {
-                    total += 1;
-                }
-            }
+        if minutes == 0 {
+            grants.remove(&user);
+            return Ok(CommandOutcome::info(format!(
+                "Removed the access of {}",
+                user
+            )));
+        }
 
-            total
+        let expiry = minutes
+            .checked_mul(60)
+            .and_then(|seconds| now.checked_add(tokio::time::Duration::from_secs(seconds)));
+        let Some(expiry) = expiry else {
+            return Ok(CommandOutcome::warning("Invalid number of minutes given"));
         };
+        grants.insert(user.to_owned(), expiry);
 
-        let mut interface = netifs.get_key_value(&name);
-
-        if interface.is_none() {
-            // get iterface name based on custom name
-            let mut possible_ip = None;
+        Ok(CommandOutcome::info(format!(
+            "{} has moderator access for {} minutes",
+            user, minutes
+        )))
+    }
 
-            // Custom name based on interface
-            for (original, custom) in &self.custom_interface_name {
-                if name == custom.to_lowercase() {
-                    interface = netifs.get_key_value(original);
-                    possible_ip = Some(original);
-                    break;
+    /// Toggles the automatic stats, or turns them on or off. A number of
+    /// seconds changes the interval and turns them on.
+    pub async fn auto_stats(&self, arg: Option<&str>) -> Result<CommandOutcome> {
+        let mut lock = self.bela_state.write().await;
+
+        match arg.map(|a| a.to_lowercase()).as_deref() {
+            None => lock.auto_stats = !lock.auto_stats,
+            Some("on") => lock.auto_stats = true,
+            Some("off") => lock.auto_stats = false,
+            Some(interval) => match interval.parse::<u64>() {
+                Ok(interval) if interval > 0 => {
+                    lock.auto_stats = true;
+                    lock.auto_stats_interval = interval;
                 }
-            }
+                _ => {
+                    return Ok(CommandOutcome::warning(
+                        "Invalid value, use on, off or seconds",
+                    ))
+                }
+            },
+        }
 
-            // Custom name based on ip
-            if interface.is_none() && possible_ip.is_some() {
-                let possible_ip = possible_ip.unwrap();
+        if !lock.auto_stats {
+            return Ok(CommandOutcome::info("Automatic stats disabled"));
+        }
 
-                for (k, v) in &netifs {
-                    if &v.ip == possible_ip {
-                        interface = netifs.get_key_value(k);
-                        break;
-                    }
+        Ok(CommandOutcome::info(format!(
+            "Automatic stats enabled every {} seconds",
+            lock.auto_stats_interval
+        )))
+    }
+
+    /// Sends a test message to every Discord webhook and log destination, so
+    /// they can be checked without waiting for a real alert.
+    pub async fn test_alert(&self) -> Result<CommandOutcome> {
+        if self.external_sinks.is_empty() {
+            return Ok(CommandOutcome::warning(
+                "No Discord webhooks or logs configured",
+            ));
+        }
+
+        let mut results = Vec::new();
+        let mut failed = false;
+        for (name, sink) in &self.external_sinks {
+            let message = format!("BB: Test alert for {}", name);
+
+            match sink.send(message).await {
+                Ok(()) => results.push(format!("{}: ok", name)),
+                Err(e) => {
+                    warn!(?e, name, "error sending the test alert");
+                    failed = true;
+                    results.push(format!("{}: failed ({})", name, e));
                 }
             }
         }
 
-        let (interface_name, interface) = match interface {
-            Some(i) => i,
+        let results = results.join(", ");
+        if failed {
+            return Ok(CommandOutcome::error(results));
+        }
+
+        Ok(CommandOutcome::info(results))
+    }
+
+    /// Shows or changes the seconds the same notification isn't announced
+    /// again, the change is saved to the config.
+    pub async fn notification_timeout(&self, arg: Option<&str>) -> Result<CommandOutcome> {
+        let Some(arg) = arg else {
+            let seconds = self.bela_state.read().await.notification_window;
+            return Ok(CommandOutcome::info(format!(
+                "Current notification timeout is {} seconds",
+                seconds
+            )));
+        };
+
+        let Ok(seconds) = arg.parse::<u64>() else {
+            return Ok(CommandOutcome::warning(format!(
+                "Invalid number {} given",
+                arg
+            )));
+        };
+
+        self.bela_state.write().await.notification_window = seconds;
+
+        if let Err(e) =
+            self.save_config(|settings| settings.belabox.monitor.notification_timeout = seconds)
+        {
+            error!(?e, "error saving the notification timeout");
+        }
+
+        Ok(CommandOutcome::info(format!(
+            "Changed the notification timeout to {} seconds",
+            seconds
+        )))
+    }
+
+    /// The round trip time to BELABOX Cloud, a slow link to the cloud also
+    /// slows down every command.
+    pub async fn ping(&self) -> Result<CommandOutcome> {
+        let (online, started, rtt, dropped) = {
+            let read = self.bela_state.read().await;
+            (
+                read.online,
+                read.started,
+                read.cloud_rtt,
+                read.dropped_messages
+                    .load(std::sync::atomic::Ordering::Relaxed),
+            )
+        };
+
+        // Confirms the bot works while the BELABOX is down
+        if !online {
+            return Ok(CommandOutcome::info(format!(
+                "Pong, the BELABOX is offline, bot uptime: {}",
+                uptime(started)
+            )));
+        }
+
+        let mut msg = match rtt {
+            Some(rtt) => format!("Cloud latency: {} ms", rtt.as_millis()),
+            None => "Cloud latency not measured yet".to_string(),
+        };
+
+        // Messages arrive faster than the bot handles them
+        if dropped > 0 {
+            let _ = write!(msg, ", dropped messages: {}", dropped);
+        }
+
+        Ok(CommandOutcome::info(msg))
+    }
+
+    /// Mutes the monitor announcements in chat for a number of seconds, `off`
+    /// unmutes them.
+    pub async fn quiet(&self, arg: Option<&str>) -> Result<CommandOutcome> {
+        let mut lock = self.bela_state.write().await;
+
+        let seconds = match arg {
+            None if !lock.is_muted() => {
+                return Ok(CommandOutcome::info("Announcements aren't muted"));
+            }
+            None => {
+                let remaining = lock
+                    .muted_until
+                    .map(|until| until - tokio::time::Instant::now())
+                    .unwrap_or_default();
+
+                return Ok(CommandOutcome::info(format!(
+                    "Announcements muted for {} more seconds",
+                    remaining.as_secs()
+                )));
+            }
+            Some(a) if a.eq_ignore_ascii_case("off") => {
+                lock.muted_until = None;
+                return Ok(CommandOutcome::info("Announcements unmuted"));
+            }
+            Some(a) => match a.parse::<u64>() {
+                Ok(s) if s > 0 => s,
+                _ => return Ok(CommandOutcome::warning("Invalid value, use off or seconds")),
+            },
+        };
+
+        let duration = tokio::time::Duration::from_secs(seconds);
+        let Some(until) = tokio::time::Instant::now().checked_add(duration) else {
+            return Ok(CommandOutcome::warning("Invalid value, use off or seconds"));
+        };
+        lock.muted_until = Some(until);
+
+        Ok(CommandOutcome::info(format!(
+            "Muted announcements for {} seconds",
+            seconds
+        )))
+    }
+
+    /// The last three notifications, newest first.
+    pub async fn notifications(&self) -> Result<CommandOutcome> {
+        let read = self.bela_state.read().await;
+
+        if read.notifications.is_empty() {
+            return Ok(CommandOutcome::info("No notifications"));
+        }
+
+        let notifications = read
+            .notifications
+            .iter()
+            .rev()
+            .take(3)
+            .map(|(time, n)| format!("{} ({} ago)", n.msg, format_age(time.elapsed())))
+            .collect::<Vec<_>>()
+            .join(" | ");
+
+        Ok(CommandOutcome::info(format!(
+            "Notifications: {}",
+            notifications
+        )))
+    }
+
+    pub async fn sync(&self) -> Result<CommandOutcome> {
+        self.belabox.sync().await?;
+        Ok(CommandOutcome::info("Requested the current BELABOX state"))
+    }
+
+    /// Disconnects the WiFi interface which is currently connected.
+    pub async fn wifi_disconnect(&self) -> Result<CommandOutcome> {
+        let Some(wifi) = self.bela_state.read().await.wifi.to_owned() else {
+            return Ok(CommandOutcome::warning("WiFi not available"));
+        };
+
+        let Some((id, ifname, uuid)) = sorted_wifi(&wifi)
+            .into_iter()
+            .find_map(|(id, w)| Some((id, &w.ifname, w.conn.as_ref()?)))
+        else {
+            return Ok(CommandOutcome::warning("Not connected to WiFi"));
+        };
+
+        if self.is_only_uplink(ifname).await {
+            return Ok(CommandOutcome::warning(
+                "Can't disconnect the only network while streaming",
+            ));
+        }
+
+        let ssid = wifi_ssid(&wifi[id], uuid);
+
+        // Subscribe before sending so the update can't be missed
+        let messages = self.belabox.message_stream()?;
+        self.belabox.wifi_disconnect(uuid.to_owned()).await?;
+
+        let dropped = self.bela_state.read().await.dropped_messages.clone();
+        let confirmed = wait_for_wifi(messages, &dropped, |wifi| {
+            wifi.get(id).is_none_or(|w| w.conn.as_ref() != Some(uuid))
+        })
+        .await;
+
+        if !confirmed {
+            return Ok(CommandOutcome::warning("WiFi disconnect not confirmed"));
+        }
+
+        Ok(CommandOutcome::info(format!(
+            "Disconnected from WiFi {}",
+            ssid
+        )))
+    }
+
+    /// Forgets a saved WiFi network by its SSID.
+    pub async fn wifi_forget<'a, I>(&self, ssid: I) -> Result<CommandOutcome>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let ssid = ssid.collect::<Vec<_>>().join(" ");
+        if ssid.is_empty() {
+            return Ok(CommandOutcome::warning("No network given"));
+        }
+
+        let Some(wifi) = self.bela_state.read().await.wifi.to_owned() else {
+            return Ok(CommandOutcome::warning("WiFi not available"));
+        };
+
+        let saved = sorted_wifi(&wifi).into_iter().find_map(|(id, w)| {
+            w.saved
+                .iter()
+                .find(|(s, _)| s.eq_ignore_ascii_case(&ssid))
+                .map(|(s, uuid)| (id, w, s, uuid))
+        });
+
+        let Some((id, w, ssid, uuid)) = saved else {
+            return Ok(CommandOutcome::warning(format!(
+                "No saved network named {}",
+                ssid
+            )));
+        };
+
+        if w.conn.as_ref() == Some(uuid) && self.is_only_uplink(&w.ifname).await {
+            return Ok(CommandOutcome::warning(
+                "Can't disconnect the only network while streaming",
+            ));
+        }
+
+        // Subscribe before sending so the update can't be missed
+        let messages = self.belabox.message_stream()?;
+        self.belabox.wifi_forget(uuid.to_owned()).await?;
+
+        let dropped = self.bela_state.read().await.dropped_messages.clone();
+        let confirmed = wait_for_wifi(messages, &dropped, |wifi| {
+            wifi.get(id)
+                .is_none_or(|w| !w.saved.values().any(|u| u == uuid))
+        })
+        .await;
+
+        if !confirmed {
+            return Ok(CommandOutcome::warning(
+                "Forgetting the WiFi network not confirmed",
+            ));
+        }
+
+        Ok(CommandOutcome::info(format!("Forgot WiFi {}", ssid)))
+    }
+
+    /// The interface is the only enabled network while streaming.
+    async fn is_only_uplink(&self, ifname: &str) -> bool {
+        let read = self.bela_state.read().await;
+        let Some(netifs) = &read.netif else {
+            return false;
+        };
+
+        read.is_streaming
+            && netifs.get(ifname).is_some_and(|i| i.enabled)
+            && !netifs.iter().any(|(name, i)| name != ifname && i.enabled)
+    }
+
+    pub async fn ssh_password(&self) -> Result<CommandOutcome> {
+        let previous = {
+            let lock = self.bela_state.read().await;
+            lock.config.as_ref().and_then(|c| c.ssh_pass.to_owned())
+        };
+
+        // Subscribe before sending so the new config can't be missed
+        let mut messages = self.belabox.message_stream()?;
+        self.belabox.reset_ssh_password().await?;
+
+        let dropped = self.bela_state.read().await.dropped_messages.clone();
+        let new_password = async {
+            while let Some(message) = crate::bot::recv(&mut messages, &dropped).await {
+                if let belabox::Event::Message(belabox::Message::Config(config)) = message {
+                    match config.ssh_pass {
+                        Some(pass) if !pass.is_empty() && Some(&pass) != previous.as_ref() => {
+                            return Some(pass);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            None
+        };
+
+        let timeout = tokio::time::Duration::from_secs(10);
+        match tokio::time::timeout(timeout, new_password).await {
+            Ok(Some(pass)) => Ok(CommandOutcome::info(format!("New SSH password: {}", pass))),
+            _ => Ok(CommandOutcome::warning("SSH password reset not confirmed")),
+        }
+    }
+
+    pub async fn bitrate(&self, bitrate: Option<&str>) -> Result<CommandOutcome> {
+        let bitrate = match bitrate {
+            Some(b) => b,
+            None => {
+                return Ok(CommandOutcome::warning("No bitrate given"));
+            }
+        };
+
+        let bitrate = match bitrate.parse::<u32>() {
+            Ok(b) => b,
+            Err(_) => {
+                return Ok(CommandOutcome::warning(format!(
+                    "Invalid number {} given",
+                    bitrate
+                )));
+            }
+        };
+
+        if !(500..=12000).contains(&bitrate) {
+            let msg = format!(
+                "Invalid value: {}, use a value between 500 - 12000",
+                bitrate
+            );
+            return Ok(CommandOutcome::warning(msg));
+        }
+
+        let bitrate = increment_by_step(bitrate as f64, 250.0) as u32;
+
+        {
+            let mut lock = self.bela_state.write().await;
+            if let Some(config) = &mut lock.config {
+                config.max_br = bitrate;
+            }
+            lock.expected_config = lock.config.to_owned();
+        }
+
+        self.belabox.bitrate(bitrate).await?;
+
+        Ok(CommandOutcome::info(format!(
+            "Changed max bitrate to {} kbps",
+            bitrate
+        )))
+    }
+
+    pub async fn network(&self, name: Option<&str>) -> Result<CommandOutcome> {
+        let name = match name {
+            Some(b) => b.to_lowercase(),
+            None => {
+                return Ok(CommandOutcome::warning("No interface given"));
+            }
+        };
+
+        let netifs = {
+            let read = self.bela_state.read().await;
+            read.netif.to_owned()
+        };
+
+        let netifs = match netifs {
+            Some(n) => n,
+            None => {
+                return Ok(CommandOutcome::warning("Interfaces not available"));
+            }
+        };
+
+        if netifs.len() == 1 {
+            return Ok(CommandOutcome::warning("You only have one connection!"));
+        }
+
+        let disabled_count = {
+            let mut total = 0;
+
+            for v in netifs.values() {
+                if !v.enabled {
+                    total += 1;
+                }
+            }
+
+            total
+        };
+
+        let interface = find_interface(&name, &netifs, &self.custom_interface_name);
+
+        let (name, interface) = match interface {
+            Some(i) => i,
             None => {
-                return Ok("Interface not found".to_string());
+                return Ok(CommandOutcome::warning("Interface not found"));
             }
         };
 
         if netifs.len() - disabled_count == 1 && interface.enabled {
-            return Ok("Can't disable all networks".to_string());
+            return Ok(CommandOutcome::warning("Can't disable all networks"));
         }
 
         let enabled = !interface.enabled;
         let network = belabox::requests::Netif {
-            name: interface_name.to_owned(),
+            name: name.to_owned(),
             ip: interface.ip.to_owned(),
             enabled,
         };
         self.belabox.netif(network).await?;
 
-        Ok(format!(
+        Ok(CommandOutcome::info(format!(
             "{} has been {}",
-            name,
+            interface_name(name, interface, &self.custom_interface_name),
             if enabled { "enabled" } else { "disabled" }
-        ))
+        )))
+    }
+
+    pub async fn overlay(&self) -> Result<CommandOutcome> {
+        let (enabled, is_streaming) = {
+            let mut lock = self.bela_state.write().await;
+            let is_streaming = lock.is_streaming;
+
+            let Some(config) = lock.config.as_mut() else {
+                return Ok(CommandOutcome::warning("Config not available"));
+            };
+
+            // There is no request to toggle the overlay while streaming, the
+            // next start will use the new value.
+            config.bitrate_overlay = !config.bitrate_overlay;
+
+            (config.bitrate_overlay, is_streaming)
+        };
+
+        let mut msg = format!(
+            "Bitrate overlay {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+
+        if is_streaming {
+            msg.push_str(", restart the stream to apply");
+        }
+
+        Ok(CommandOutcome::info(msg))
+    }
+
+    /// A summary of the state, versions and stream settings of the BELABOX,
+    /// the first thing to check when there is a problem.
+    pub async fn info(&self) -> Result<CommandOutcome> {
+        let state = self.bela_state.read().await;
+
+        let mut parts = vec![
+            if state.online { "Online" } else { "Offline" }.to_string(),
+            if state.is_streaming {
+                "streaming"
+            } else {
+                "not streaming"
+            }
+            .to_string(),
+        ];
+
+        if let Some(config) = &state.config {
+            let pipeline = state
+                .pipelines
+                .as_ref()
+                .and_then(|p| p.get(&config.pipeline))
+                .map_or("unknown", |p| split_pipeline(&p.name).1);
+
+            parts.push(format!("Pipeline: {}", pipeline));
+            parts.push(format!("Audio: {}", config.asrc));
+            parts.push(format!("Bitrate: {} kbps", config.max_br));
+            parts.push(format!("Latency: {} ms", config.srt_latency));
+        }
+
+        if let Some(revisions) = &state.revisions {
+            parts.push(format!("Image: {}", revisions.belabox_image));
+            parts.push(format!("belaUI: {}", revisions.bela_ui));
+            parts.push(format!("belacoder: {}", revisions.belacoder));
+            parts.push(format!("srtla: {}", revisions.srtla));
+        }
+
+        if let Some(revision) = &state.remote_revision {
+            parts.push(format!("Cloud: {}", revision));
+        }
+
+        Ok(CommandOutcome::info(parts.join(", ")))
     }
 
-    pub async fn sensor(&self) -> Result<String> {
+    /// The version of the bot and how long it has been running, to check if
+    /// it restarted.
+    pub async fn bot(&self) -> Result<CommandOutcome> {
+        let started = self.bela_state.read().await.started;
+
+        Ok(CommandOutcome::info(format!(
+            "belabot v{}, uptime: {}",
+            env!("CARGO_PKG_VERSION"),
+            uptime(started)
+        )))
+    }
+
+    /// Shows if the BELABOX is streaming and if SSH is running.
+    pub async fn status(&self) -> Result<CommandOutcome> {
+        let (is_streaming, ssh) = {
+            let lock = self.bela_state.read().await;
+            (lock.is_streaming, lock.ssh.to_owned())
+        };
+
+        let streaming = if is_streaming { "yes" } else { "no" };
+        let ssh = match ssh {
+            Some(ssh) if ssh.active => format!("on ({})", ssh.user),
+            Some(_) => "off".to_string(),
+            None => "unknown".to_string(),
+        };
+
+        Ok(CommandOutcome::info(format!(
+            "Streaming: {}, SSH: {}",
+            streaming, ssh
+        )))
+    }
+
+    pub async fn sensor(&self) -> Result<CommandOutcome> {
         let sensors = {
             let read = self.bela_state.read().await;
             read.sensors.to_owned()
@@ -355,7 +1402,7 @@ impl CommandHandler {
         let sensors = match sensors {
             Some(s) => s,
             None => {
-                return Ok("Sensors not available".to_string());
+                return Ok(CommandOutcome::warning("Sensors not available"));
             }
         };
 
@@ -375,10 +1422,10 @@ impl CommandHandler {
             let _ = write!(response, ", Amps: {}", current);
         }
 
-        Ok(response)
+        Ok(CommandOutcome::info(response))
     }
 
-    pub async fn latency(&self, latency: Option<&str>) -> Result<String> {
+    pub async fn latency(&self, latency: Option<&str>) -> Result<CommandOutcome> {
         let latency = match latency {
             Some(b) => b,
             None => {
@@ -397,47 +1444,73 @@ impl CommandHandler {
                     "unknown".to_string()
                 };
 
-                return Ok(format!("Current SRT latency is {} ms", latency));
-            }
-        };
-
-        let latency = match latency.parse::<u64>() {
-            Ok(l) => l,
-            Err(_) => {
-                return Ok(format!("Invalid number {} given", latency));
+                return Ok(CommandOutcome::info(format!(
+                    "Current SRT latency is {} ms",
+                    latency
+                )));
             }
         };
 
-        if !(100..=4000).contains(&latency) {
-            let msg = format!("Invalid value: {}, use a value between 100 - 4000", latency);
-            return Ok(msg);
-        }
+        // A sign changes the latency relative to the current one
+        let latency = if latency.starts_with(['+', '-']) {
+            let delta = match latency.parse::<i64>() {
+                Ok(d) => d,
+                Err(_) => {
+                    return Ok(CommandOutcome::warning(format!(
+                        "Invalid number {} given",
+                        latency
+                    )));
+                }
+            };
 
-        let latency = increment_by_step(latency as f64, 100.0);
-        let is_streaming = { self.bela_state.read().await.is_streaming };
+            let current_latency = {
+                self.bela_state
+                    .read()
+                    .await
+                    .config
+                    .as_ref()
+                    .map(|config| config.srt_latency)
+            };
 
-        if is_streaming {
-            let _ = self.stop().await?;
-            self.send("Restarting the stream".to_string()).await;
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await
-        }
+            let Some(current) = current_latency else {
+                return Ok(CommandOutcome::warning("Current SRT latency is unknown"));
+            };
 
-        {
-            let mut lock = self.bela_state.write().await;
+            (current as i64 + delta).clamp(100, 4000) as u64
+        } else {
+            let latency = match latency.parse::<u64>() {
+                Ok(l) => l,
+                Err(_) => {
+                    return Ok(CommandOutcome::warning(format!(
+                        "Invalid number {} given",
+                        latency
+                    )));
+                }
+            };
 
-            if let Some(config) = &mut lock.config {
-                config.srt_latency = latency as u64;
+            if !(100..=4000).contains(&latency) {
+                let msg = format!("Invalid value: {}, use a value between 100 - 4000", latency);
+                return Ok(CommandOutcome::warning(msg));
             }
-        }
 
-        if is_streaming {
-            let _ = self.start().await?;
+            latency
+        };
+
+        let latency = increment_by_step(latency as f64, 100.0);
+        if let Some(refused) = self
+            .update_config(|config| config.srt_latency = latency as u64)
+            .await?
+        {
+            return Ok(refused);
         }
 
-        Ok(format!("Changed SRT latency to {} ms", latency))
+        Ok(CommandOutcome::info(format!(
+            "Changed SRT latency to {} ms",
+            latency
+        )))
     }
 
-    pub async fn audio_delay(&self, delay: Option<&str>) -> Result<String> {
+    pub async fn audio_delay(&self, delay: Option<&str>) -> Result<CommandOutcome> {
         let delay = match delay {
             Some(b) => b,
             None => {
@@ -456,174 +1529,823 @@ impl CommandHandler {
                     "unknown".to_string()
                 };
 
-                return Ok(format!("Current audio delay is {} ms", delay));
+                return Ok(CommandOutcome::info(format!(
+                    "Current audio delay is {} ms",
+                    delay
+                )));
             }
         };
 
+        let relative = delay.starts_with(['+', '-']);
         let delay = match delay.parse::<i32>() {
             Ok(l) => l,
             Err(_) => {
-                return Ok(format!("Invalid number {} given", delay));
+                return Ok(CommandOutcome::warning(format!(
+                    "Invalid number {} given",
+                    delay
+                )));
             }
         };
 
-        if delay.abs() > 2000 {
-            let msg = format!("Invalid value: {}, use a value between -2000 - 2000", delay);
-            return Ok(msg);
-        }
-
-        let delay = increment_by_step(delay, 20.0);
-        let is_streaming = { self.bela_state.read().await.is_streaming };
-
-        if is_streaming {
-            let _ = self.stop().await?;
-            self.send("Restarting the stream".to_string()).await;
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await
-        }
+        // A sign changes the delay relative to the current one
+        let delay = if relative {
+            let current_delay = {
+                self.bela_state
+                    .read()
+                    .await
+                    .config
+                    .as_ref()
+                    .map(|config| config.delay)
+            };
 
-        {
-            let mut lock = self.bela_state.write().await;
+            let Some(current) = current_delay else {
+                return Ok(CommandOutcome::warning("Current audio delay is unknown"));
+            };
 
-            if let Some(config) = &mut lock.config {
-                config.delay = delay as i32;
+            current.saturating_add(delay).clamp(-2000, 2000)
+        } else {
+            if delay.abs() > 2000 {
+                let msg = format!("Invalid value: {}, use a value between -2000 - 2000", delay);
+                return Ok(CommandOutcome::warning(msg));
             }
-        }
 
-        if is_streaming {
-            let _ = self.start().await?;
+            delay
+        };
+
+        let delay = increment_by_step(delay, 20.0);
+        if let Some(refused) = self
+            .update_config(|config| config.delay = delay as i32)
+            .await?
+        {
+            return Ok(refused);
         }
 
-        Ok(format!("Changed audio delay to {} ms", delay))
+        Ok(CommandOutcome::info(format!(
+            "Changed audio delay to {} ms",
+            delay
+        )))
     }
 
-    pub(crate) async fn pipeline<'a, I>(&self, args: I) -> Result<String>
+    pub(crate) async fn pipeline<'a, I>(&self, args: I) -> Result<CommandOutcome>
     where
         I: IntoIterator<Item = &'a str>,
     {
         let args = args.into_iter();
         let query = args.collect::<Vec<&str>>().join(" ");
 
-        let (is_streaming, pipelines) = {
+        let found_pipeline = {
             let state = self.bela_state.read().await;
-            let current_pipeline = state.config.as_ref().map(|config| &config.pipeline);
-            let mut pipelines = Vec::new();
-
-            if let (Some(all_pipelines), Some(current)) = (&state.pipelines, current_pipeline) {
-                // Should always contain a "/" and the current pipeline
-                let current = all_pipelines
-                    .get(current)
-                    .unwrap()
-                    .name
-                    .split('/')
-                    .next()
-                    .unwrap();
-
-                pipelines = all_pipelines
-                    .iter()
-                    .filter(|(_, v)| v.name.contains(current))
-                    .map(|(k, v)| (k.to_string(), v.name.split('/').nth(1).unwrap().to_owned()))
-                    .collect();
+
+            let Some(all_pipelines) = &state.pipelines else {
+                return Ok(CommandOutcome::warning("Pipelines not available"));
             };
 
-            (state.is_streaming, pipelines)
-        };
+            let current = state
+                .config
+                .as_ref()
+                .and_then(|config| all_pipelines.get(&config.pipeline));
 
-        if is_streaming {
-            let _ = self.stop().await?;
-            self.send("Restarting the stream".to_string()).await;
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await
-        }
+            let Some(current) = current else {
+                return Ok(CommandOutcome::warning("Current pipeline unknown"));
+            };
 
-        // find pipeline
-        let found_pipeline = pipelines
-            .iter()
-            .map(|(h, p)| {
-                let pl = p.to_lowercase().replace('_', " ");
-                ((h, p), strsim::sorensen_dice(&query, &pl))
-            })
-            //     .collect::<Vec<(f64, (String, String))>>();
-            .min_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+            let (device, name) = split_pipeline(&current.name);
 
-        let found_pipeline = match found_pipeline {
-            Some(p) => p,
-            None => return Ok("Pipeline not found".to_string()),
-        };
+            if query.is_empty() {
+                return Ok(CommandOutcome::info(format!(
+                    "Current pipeline is {}",
+                    name
+                )));
+            }
 
-        if found_pipeline.1 == 0.0 {
-            return Ok("Pipeline not found".to_string());
-        }
+            match alias(&self.pipeline_aliases, &query) {
+                Some(hash) => all_pipelines.get_key_value(hash).ok_or_else(Vec::new),
+                None => find_pipeline(&query, Some(device), all_pipelines, self.match_threshold),
+            }
+            .map(|(hash, p)| (hash.to_owned(), split_pipeline(&p.name).1.to_owned()))
+        };
 
-        // change pipeline
-        {
-            let mut state = self.bela_state.write().await;
-            if let Some(config) = state.config.as_mut() {
-                config.pipeline = found_pipeline.0 .0.to_owned();
+        let (hash, name) = match found_pipeline {
+            Ok(p) => p,
+            Err(suggestions) => {
+                return Ok(CommandOutcome::warning(no_match("Pipeline", suggestions)))
             }
-        }
+        };
 
-        if is_streaming {
-            let _ = self.start().await?;
+        // change pipeline
+        if let Some(refused) = self.update_config(|config| config.pipeline = hash).await? {
+            return Ok(refused);
         }
 
-        Ok(format!("Changed pipeline to {}", found_pipeline.0 .1))
+        Ok(CommandOutcome::info(format!(
+            "Changed pipeline to {}",
+            name
+        )))
     }
 
-    pub(crate) async fn audio_src<'a, I>(&self, args: I) -> Result<String>
+    /// Shows, lists or selects the relay server by number, `account` as the
+    /// first argument does the same for the relay account.
+    pub(crate) async fn relay<'a, I>(&self, args: I) -> Result<CommandOutcome>
     where
         I: IntoIterator<Item = &'a str>,
     {
-        let args = args.into_iter();
-        let query = args.collect::<Vec<&str>>().join(" ");
+        let mut args = args.into_iter().peekable();
+        let account = args
+            .next_if(|a| a.eq_ignore_ascii_case("account"))
+            .is_some();
+        let kind = if account { "account" } else { "server" };
 
-        let (is_streaming, asrcs) = {
+        let (relays, current) = {
             let state = self.bela_state.read().await;
-            let asrcs = state.asrcs.to_owned();
 
-            (state.is_streaming, asrcs)
-        };
+            let Some(relays) = &state.relays else {
+                return Ok(CommandOutcome::warning("Relays not available"));
+            };
 
-        if is_streaming {
-            let _ = self.stop().await?;
-            self.send("Restarting the stream".to_string()).await;
-            tokio::time::sleep(tokio::time::Duration::from_secs(5)).await
+            let relays = if account {
+                sorted_relays(relays.accounts.iter().map(|(id, a)| (id, &a.name)))
+            } else {
+                sorted_relays(relays.servers.iter().map(|(id, s)| (id, &s.name)))
+            };
+
+            let current = state.config.as_ref().map(|c| {
+                if account {
+                    c.relay_account.to_owned()
+                } else {
+                    c.relay_server.to_owned()
+                }
+            });
+
+            (relays, current)
+        };
+
+        let Some(arg) = args.next() else {
+            let current = relays
+                .iter()
+                .find(|(id, _)| Some(id) == current.as_ref())
+                .map(|(_, name)| name.as_str())
+                .unwrap_or("none");
+
+            return Ok(CommandOutcome::info(format!(
+                "Current relay {} is {}",
+                kind, current
+            )));
+        };
+
+        if arg.eq_ignore_ascii_case("list") {
+            if relays.is_empty() {
+                return Ok(CommandOutcome::info(format!("No relay {}s", kind)));
+            }
+
+            let list = relays
+                .iter()
+                .enumerate()
+                .map(|(index, (_, name))| format!("{}) {}", index + 1, name))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            let list = format!("Relay {}s: {}", kind, list);
+            return Ok(CommandOutcome::info(list).list(", "));
         }
 
+        let Ok(number) = arg.parse::<usize>() else {
+            return Ok(CommandOutcome::warning(
+                "Use list or the number of the relay",
+            ));
+        };
+
+        let Some((id, name)) = number.checked_sub(1).and_then(|i| relays.get(i)).cloned() else {
+            return Ok(CommandOutcome::warning(format!(
+                "Relay {} {} not found",
+                kind, number
+            )));
+        };
+
+        if let Some(refused) = self
+            .update_config(|config| {
+                if account {
+                    config.relay_account = id;
+                } else {
+                    config.relay_server = id;
+                }
+            })
+            .await?
+        {
+            return Ok(refused);
+        }
+
+        Ok(CommandOutcome::info(format!(
+            "Changed relay {} to {}",
+            kind, name
+        )))
+    }
+
+    pub(crate) async fn pipeline_info<'a, I>(&self, args: I) -> Result<CommandOutcome>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let args = args.into_iter();
+        let query = args.collect::<Vec<&str>>().join(" ");
+
+        let state = self.bela_state.read().await;
+
+        let Some(all_pipelines) = &state.pipelines else {
+            return Ok(CommandOutcome::warning("Pipelines not available"));
+        };
+
+        let current = state
+            .config
+            .as_ref()
+            .and_then(|config| all_pipelines.get(&config.pipeline));
+
+        let pipeline = if query.is_empty() {
+            current.ok_or_else(Vec::new)
+        } else if let Some(hash) = alias(&self.pipeline_aliases, &query) {
+            all_pipelines.get(hash).ok_or_else(Vec::new)
+        } else {
+            let device = current.map(|c| split_pipeline(&c.name).0);
+            find_pipeline(&query, device, all_pipelines, self.match_threshold).map(|(_, p)| p)
+        };
+
+        let pipeline = match pipeline {
+            Ok(p) => p,
+            Err(suggestions) => {
+                return Ok(CommandOutcome::warning(no_match("Pipeline", suggestions)))
+            }
+        };
+
+        let available = |a| if a { "available" } else { "not available" };
+
+        Ok(CommandOutcome::info(format!(
+            "{}: audio source selection {}, audio codec selection {}",
+            split_pipeline(&pipeline.name).1,
+            available(pipeline.asrc),
+            available(pipeline.acodec)
+        )))
+    }
+
+    pub(crate) async fn audio_src<'a, I>(&self, args: I) -> Result<CommandOutcome>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let args = args.into_iter();
+        let query = args.collect::<Vec<&str>>().join(" ");
+
+        if query.is_empty() {
+            let current_asrc = {
+                self.bela_state
+                    .read()
+                    .await
+                    .config
+                    .as_ref()
+                    .map(|config| config.asrc.to_owned())
+            };
+
+            let asrc = current_asrc.unwrap_or_else(|| "unknown".to_string());
+
+            return Ok(CommandOutcome::info(format!(
+                "Current audio source is {}",
+                asrc
+            )));
+        }
+
+        let asrcs = { self.bela_state.read().await.asrcs.to_owned() };
+
         let asrcs = match asrcs {
             Some(a) => a,
-            None => return Ok("No audio sources found".to_string()),
+            None => return Ok(CommandOutcome::warning("No audio sources found")),
         };
 
         // find audio src
-        let found_asrcs = asrcs
-            .iter()
-            .map(|asrc| (asrc, strsim::sorensen_dice(&query, &asrc.to_lowercase())))
-            .min_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        let asrc = match alias(&self.asrc_aliases, &query) {
+            Some(alias) => asrcs.iter().find(|a| *a == alias).ok_or_else(Vec::new),
+            None => {
+                let candidates = asrcs.iter().map(|asrc| (asrc, asrc.to_owned()));
+                best_match(&query, candidates, self.match_threshold)
+            }
+        };
 
-        let found_asrcs = match found_asrcs {
-            Some(p) => p,
-            None => return Ok("Audio source not found".to_string()),
+        let asrc = match asrc {
+            Ok(asrc) => asrc,
+            Err(suggestions) => {
+                return Ok(CommandOutcome::warning(no_match(
+                    "Audio source",
+                    suggestions,
+                )))
+            }
         };
 
-        if found_asrcs.1 == 0.0 {
-            return Ok("Audio source not found".to_string());
+        // change audio src
+        if let Some(refused) = self
+            .update_config(|config| config.asrc = asrc.to_owned())
+            .await?
+        {
+            return Ok(refused);
         }
 
-        // change audio src
+        Ok(CommandOutcome::info(format!("Changed audio to {}", asrc)))
+    }
+
+    /// Shows or changes the audio codec, when the current pipeline supports
+    /// it. belaUI has no setting for the audio bitrate so a given bitrate is
+    /// only reported back as not applied.
+    pub(crate) async fn audio_codec<'a, I>(&self, args: I) -> Result<CommandOutcome>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut args = args.into_iter();
+
+        let (current, supported) = {
+            let state = self.bela_state.read().await;
+            let Some(config) = &state.config else {
+                return Ok(CommandOutcome::warning("Config not available"));
+            };
+
+            let supported = state
+                .pipelines
+                .as_ref()
+                .and_then(|p| p.get(&config.pipeline))
+                .map(|p| p.acodec);
+
+            (config.acodec.to_owned(), supported)
+        };
+
+        let Some(codec) = args.next().map(|c| c.to_lowercase()) else {
+            return Ok(CommandOutcome::info(format!(
+                "Current audio codec is {}",
+                current
+            )));
+        };
+
+        if supported == Some(false) {
+            return Ok(CommandOutcome::warning(
+                "The current pipeline doesn't support changing the audio codec",
+            ));
+        }
+
+        if !AUDIO_CODECS.contains(&codec.as_str()) {
+            return Ok(CommandOutcome::warning(format!(
+                "Unknown audio codec {}, use {}",
+                codec,
+                AUDIO_CODECS.join(" or ")
+            )));
+        }
+
+        if let Some(refused) = self
+            .update_config(|config| config.acodec = codec.to_owned())
+            .await?
         {
-            let mut state = self.bela_state.write().await;
-            if let Some(config) = state.config.as_mut() {
-                config.asrc = found_asrcs.0.to_owned();
+            return Ok(refused);
+        }
+
+        let mut msg = format!("Changed audio codec to {}", codec);
+        if let Some(bitrate) = args.next() {
+            let _ = write!(
+                msg,
+                ", the audio bitrate of {} can't be set in belaUI",
+                bitrate
+            );
+        }
+
+        Ok(CommandOutcome::info(msg))
+    }
+
+    pub async fn defaults(&self) -> Result<CommandOutcome> {
+        let config::Defaults {
+            bitrate,
+            latency,
+            delay,
+        } = self.defaults;
+
+        if let Some(refused) = self
+            .update_config(|config| {
+                config.max_br = bitrate;
+                config.srt_latency = latency;
+                config.delay = delay;
+            })
+            .await?
+        {
+            return Ok(refused);
+        }
+
+        Ok(CommandOutcome::info(format!(
+            "Reset to {} kbps, {} ms SRT latency and {} ms audio delay",
+            bitrate, latency, delay
+        )))
+    }
+
+    pub(crate) async fn preset<'a, I>(&self, args: I) -> Result<CommandOutcome>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut args = args.into_iter();
+        let action = args.next().map(|a| a.to_lowercase());
+        let name = args.collect::<Vec<&str>>().join(" ").to_lowercase();
+
+        match action.as_deref() {
+            None => {
+                let mut names = self
+                    .presets
+                    .read()
+                    .await
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<_>>();
+
+                if names.is_empty() {
+                    return Ok(CommandOutcome::info("No presets saved"));
+                }
+
+                names.sort();
+                let names = format!("Presets: {}", names.join(", "));
+                Ok(CommandOutcome::info(names).list(", "))
             }
+            Some("save") if !name.is_empty() => self.save_preset(name).await,
+            Some("load") if !name.is_empty() => self.load_preset(name).await,
+            _ => Ok(CommandOutcome::warning(
+                "Usage: save <name>, load <name> or no arguments to list",
+            )),
         }
+    }
 
-        if is_streaming {
-            let _ = self.start().await?;
+    /// Changes who can use a command, the command is found by its name or
+    /// its trigger.
+    pub(crate) async fn permission<'a, I>(&self, args: I) -> Result<CommandOutcome>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut args = args.into_iter();
+        let (Some(name), Some(level)) = (args.next(), args.next()) else {
+            return Ok(CommandOutcome::warning(
+                "Usage: <command> <Broadcaster/Moderator/Vip/Subscriber/Public>",
+            ));
+        };
+
+        let Some(permission) = Permission::from_name(level) else {
+            return Ok(CommandOutcome::warning(format!(
+                "Unknown permission {}, use Broadcaster, Moderator, Vip, Subscriber or Public",
+                level
+            )));
+        };
+
+        let commands = {
+            let mut commands = self.commands.write().await;
+
+            let info = commands.iter_mut().find(|(command, info)| {
+                format!("{:?}", command).eq_ignore_ascii_case(name)
+                    || info.command.eq_ignore_ascii_case(name)
+            });
+
+            let Some((_, info)) = info else {
+                return Ok(CommandOutcome::warning(format!("Unknown command {}", name)));
+            };
+
+            info.permission = permission.to_owned();
+            commands.to_owned()
+        };
+
+        if let Err(e) = self.save_config(|settings| settings.commands = commands) {
+            error!(?e, "error saving the permission");
+        }
+
+        Ok(CommandOutcome::info(format!(
+            "Changed the permission of {} to {:?}",
+            name, permission
+        )))
+    }
+
+    async fn save_preset(&self, name: String) -> Result<CommandOutcome> {
+        let preset = {
+            let state = self.bela_state.read().await;
+            match &state.config {
+                Some(config) => config::Preset::from(config),
+                None => return Ok(CommandOutcome::warning("Config not available")),
+            }
+        };
+
+        let presets = {
+            let mut presets = self.presets.write().await;
+            presets.insert(name.to_owned(), preset);
+            presets.to_owned()
+        };
+
+        if let Err(e) = self.save_config(|settings| settings.presets = presets) {
+            error!(?e, "error saving presets");
+        }
+
+        Ok(CommandOutcome::info(format!("Saved preset {}", name)))
+    }
+
+    async fn load_preset(&self, name: String) -> Result<CommandOutcome> {
+        let preset = match self.presets.read().await.get(&name) {
+            Some(p) => p.to_owned(),
+            None => {
+                return Ok(CommandOutcome::warning(format!(
+                    "Preset {} not found",
+                    name
+                )))
+            }
+        };
+
+        if let Some(refused) = self.update_config(|config| preset.apply(config)).await? {
+            return Ok(refused);
+        }
+
+        Ok(CommandOutcome::info(format!("Loaded preset {}", name)))
+    }
+}
+
+/// The audio codecs belaUI can encode with.
+const AUDIO_CODECS: [&str; 2] = ["opus", "aac"];
+
+/// The pause between the parts of a split reply.
+const SPLIT_REPLY_DELAY: tokio::time::Duration = tokio::time::Duration::from_millis(1500);
+
+/// Cuts the message at a word boundary so it fits in `max` chars, together
+/// with the number of words left out, e.g. `usb0: 2500 kbps, …(+6 more)`.
+fn truncate_reply(message: &str, max: usize) -> String {
+    if message.chars().count() <= max {
+        return message.to_owned();
+    }
+
+    let words = message.split(' ').collect::<Vec<_>>();
+    let mut kept = words.len();
+
+    loop {
+        kept -= 1;
+
+        let suffix = format!("…(+{} more)", words.len() - kept);
+        let truncated = words[..kept].join(" ");
+        if kept == 0 {
+            return suffix;
         }
 
-        Ok(format!("Changed audio to {}", found_asrcs.0))
+        if truncated.chars().count() + 1 + suffix.chars().count() <= max {
+            return format!("{} {}", truncated, suffix);
+        }
     }
 }
 
+/// Splits the message at the delimiter into parts of at most `max` chars,
+/// items longer than that are split at words and words at chars.
+fn split_reply(message: &str, max: usize, delimiter: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let len = |s: &str| s.chars().count();
+
+    for item in message.split(delimiter) {
+        if len(item) > max {
+            if !part.is_empty() {
+                parts.push(std::mem::take(&mut part));
+            }
+
+            let mut pieces = if delimiter != " " {
+                split_reply(item, max, " ")
+            } else {
+                let chars = item.chars().collect::<Vec<_>>();
+                chars.chunks(max.max(1)).map(String::from_iter).collect()
+            };
+
+            part = pieces.pop().unwrap_or_default();
+            parts.append(&mut pieces);
+            continue;
+        }
+
+        if !part.is_empty() && len(&part) + len(delimiter) + len(item) > max {
+            parts.push(std::mem::take(&mut part));
+        }
+
+        if !part.is_empty() {
+            part.push_str(delimiter);
+        }
+
+        part.push_str(item);
+    }
+
+    parts.push(part);
+    parts
+}
+
+/// Splits a pipeline name such as `jetson/h265_camlink_1080p` into the device
+/// and the name of the pipeline.
+pub(crate) fn split_pipeline(name: &str) -> (&str, &str) {
+    name.split_once('/').unwrap_or(("", name))
+}
+
+/// Finds the pipeline which best matches the query, when a device is given
+/// only pipelines for that device are considered.
+fn find_pipeline<'a>(
+    query: &str,
+    device: Option<&str>,
+    pipelines: &'a HashMap<String, belabox::messages::Pipeline>,
+    threshold: f64,
+) -> Result<(&'a String, &'a belabox::messages::Pipeline), Vec<String>> {
+    let candidates = pipelines
+        .iter()
+        .filter(|(_, p)| device.is_none_or(|d| split_pipeline(&p.name).0 == d))
+        .map(|(h, p)| ((h, p), split_pipeline(&p.name).1.to_owned()));
+
+    best_match(query, candidates, threshold)
+}
+
+/// The target of the alias matching the query, ignoring case.
+fn alias<'a>(aliases: &'a HashMap<String, String>, query: &str) -> Option<&'a str> {
+    aliases
+        .iter()
+        .find(|(alias, _)| alias.eq_ignore_ascii_case(query))
+        .map(|(_, target)| target.as_str())
+}
+
+/// The candidate with the name most similar to the query. When none is at
+/// least as similar as the threshold, the names of the three closest
+/// candidates are returned instead.
+fn best_match<T>(
+    query: &str,
+    candidates: impl IntoIterator<Item = (T, String)>,
+    threshold: f64,
+) -> Result<T, Vec<String>> {
+    let normalize = |s: &str| s.to_lowercase().replace('_', " ");
+    let query = normalize(query);
+
+    let mut scored = candidates
+        .into_iter()
+        .map(|(c, name)| {
+            let score = strsim::sorensen_dice(&query, &normalize(&name));
+            (c, name, score)
+        })
+        .filter(|(_, _, score)| *score > 0.0)
+        .collect::<Vec<_>>();
+    scored.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+    if scored
+        .first()
+        .is_some_and(|(_, _, score)| *score >= threshold)
+    {
+        return Ok(scored.swap_remove(0).0);
+    }
+
+    Err(scored
+        .into_iter()
+        .take(3)
+        .map(|(_, name, _)| name)
+        .collect())
+}
+
+/// The reply when nothing matched closely enough.
+fn no_match(what: &str, suggestions: Vec<String>) -> String {
+    if suggestions.is_empty() {
+        return format!("{} not found", what);
+    }
+
+    format!("No close match, did you mean: {}?", suggestions.join(", "))
+}
+
+/// Interfaces sorted by name, this order is used everywhere interfaces are
+/// listed and the position is the number of the interface.
+pub(crate) fn sorted_interfaces(
+    netifs: &HashMap<String, belabox::messages::Netif>,
+) -> Vec<(&String, &belabox::messages::Netif)> {
+    let mut interfaces = netifs.iter().collect::<Vec<_>>();
+    interfaces.sort_by(|a, b| a.0.cmp(b.0));
+    interfaces
+}
+
+/// The name an interface is shown with, a custom name based on its IP takes
+/// precedence over one based on its interface name.
+pub(crate) fn interface_name<'a>(
+    name: &'a str,
+    netif: &belabox::messages::Netif,
+    custom_interface_name: &'a HashMap<String, String>,
+) -> &'a str {
+    custom_interface_name
+        .get(&netif.ip)
+        .or_else(|| custom_interface_name.get(name))
+        .map_or(name, String::as_str)
+}
+
+/// A short age like 45s, 12m or 3h.
+fn format_age(age: tokio::time::Duration) -> String {
+    let secs = age.as_secs();
+
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        _ => format!("{}h", secs / 3600),
+    }
+}
+
+/// The time since the start in hours and minutes.
+fn uptime(started: tokio::time::Instant) -> String {
+    let minutes = started.elapsed().as_secs() / 60;
+
+    format!("{}h {}m", minutes / 60, minutes % 60)
+}
+
+/// The relay ids and names sorted by name, the order of the numbers in the
+/// relay command.
+fn sorted_relays<'a>(
+    relays: impl Iterator<Item = (&'a String, &'a String)>,
+) -> Vec<(String, String)> {
+    let mut relays = relays
+        .map(|(id, name)| (id.to_owned(), name.to_owned()))
+        .collect::<Vec<_>>();
+    relays.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+    relays
+}
+
+/// The WiFi interfaces sorted by their device id.
+fn sorted_wifi(
+    wifi: &HashMap<String, belabox::messages::Wifi>,
+) -> Vec<(&String, &belabox::messages::Wifi)> {
+    let mut wifi = wifi.iter().collect::<Vec<_>>();
+    wifi.sort_by(|a, b| a.0.cmp(b.0));
+    wifi
+}
+
+/// The SSID of a saved connection, falls back to the interface name.
+fn wifi_ssid<'a>(wifi: &'a belabox::messages::Wifi, uuid: &str) -> &'a str {
+    wifi.saved
+        .iter()
+        .find(|(_, u)| *u == uuid)
+        .map(|(ssid, _)| ssid)
+        .unwrap_or(&wifi.ifname)
+}
+
+/// Waits up to 10 seconds for a WiFi update matching the condition.
+async fn wait_for_wifi<F>(
+    mut messages: broadcast::Receiver<belabox::Event>,
+    dropped: &AtomicU64,
+    condition: F,
+) -> bool
+where
+    F: Fn(&HashMap<String, belabox::messages::Wifi>) -> bool,
+{
+    use belabox::{messages::StatusKind, Event, Message};
+
+    let confirmed = async {
+        while let Some(event) = crate::bot::recv(&mut messages, dropped).await {
+            let Event::Message(message) = event else {
+                continue;
+            };
+
+            let wifi = match message {
+                Message::Wifi(w) | Message::Status(StatusKind::Wifi(w)) => w.wifi,
+                Message::Status(StatusKind::Status(s)) => s.wifi,
+                _ => continue,
+            };
+
+            if condition(&wifi) {
+                return true;
+            }
+        }
+
+        false
+    };
+
+    let timeout = tokio::time::Duration::from_secs(10);
+    tokio::time::timeout(timeout, confirmed)
+        .await
+        .unwrap_or(false)
+}
+
+/// Finds an interface by its name, custom name or number, the custom name can
+/// either be based on the interface name or its IP. All lookups are
+/// case-insensitive.
+fn find_interface<'a>(
+    query: &str,
+    netifs: &'a HashMap<String, belabox::messages::Netif>,
+    custom_interface_name: &HashMap<String, String>,
+) -> Option<(&'a String, &'a belabox::messages::Netif)> {
+    let query = query.to_lowercase();
+
+    // Interface name
+    if let Some(interface) = netifs.iter().find(|(name, _)| name.to_lowercase() == query) {
+        return Some(interface);
+    }
+
+    // Custom name based on interface or IP
+    let custom = custom_interface_name
+        .iter()
+        .filter(|(_, custom)| custom.to_lowercase() == query)
+        .find_map(|(original, _)| {
+            let original = original.to_lowercase();
+
+            netifs
+                .iter()
+                .find(|(name, i)| name.to_lowercase() == original || i.ip == original)
+        });
+
+    if custom.is_some() {
+        return custom;
+    }
+
+    // Interface number
+    let number = query.parse::<usize>().ok()?;
+    sorted_interfaces(netifs)
+        .get(number.checked_sub(1)?)
+        .copied()
+}
+
 fn increment_by_step<V, S>(value: V, step: S) -> f64
 where
     V: Into<f64>,
@@ -634,3 +2356,1354 @@ where
 
     (value / step).round() * step
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::belabox::mock::{self, MockServer};
+
+    /// Keeps every message the bot sends.
+    #[derive(Default)]
+    struct TestSink(std::sync::Mutex<Vec<String>>);
+
+    #[async_trait::async_trait]
+    impl MessageSink for TestSink {
+        async fn send(&self, message: String) -> Result<()> {
+            self.0.lock().unwrap().push(message);
+            Ok(())
+        }
+    }
+
+    /// Creates a command handler connected to the mock server, waits until
+    /// the config has been received.
+    pub(crate) async fn handler(server: &mut MockServer) -> CommandHandler {
+        let belabox = Arc::new(
+            Belabox::connect("remote_key".to_string(), server.url.to_owned())
+                .await
+                .unwrap(),
+        );
+
+        // auth
+        server.request().await;
+
+        let sink = Arc::new(TestSink::default());
+        let bela_state = Arc::new(RwLock::new(BelaState::default()));
+
+        tokio::spawn(crate::bot::handle_belabox_messages(
+            belabox.message_stream().unwrap(),
+            belabox.clone(),
+            sink.clone(),
+            bela_state.clone(),
+        ));
+
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            while bela_state.read().await.asrcs.is_none() {
+                tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("timed out waiting for the state");
+
+        CommandHandler {
+            sink,
+            belabox,
+            bela_state,
+            commands: Default::default(),
+            custom_interface_name: HashMap::new(),
+            pipeline_aliases: HashMap::new(),
+            asrc_aliases: HashMap::new(),
+            number_interfaces: false,
+            stats_temperature: false,
+            stats_target: false,
+            monitor_stats_format: Vec::new(),
+            admins: Vec::new(),
+            grants: Default::default(),
+            grant_duration: 60,
+            offline_message: "Offline :(".to_string(),
+            offline_cooldown: 30,
+            last_offline_reply: Default::default(),
+            last_used: Default::default(),
+            presets: Arc::new(RwLock::new(HashMap::new())),
+            command_log: Vec::new(),
+            external_sinks: Vec::new(),
+            max_reply_length: 500,
+            split_replies: false,
+            config_path: None,
+            defaults: config::Defaults::default(),
+            restart_delay: 5,
+            match_threshold: 0.3,
+            reboot_timeout: 300,
+            operation: Default::default(),
+        }
+    }
+
+    pub(crate) async fn server() -> MockServer {
+        MockServer::start(vec![
+            mock::ENCODER_ONLINE,
+            mock::CONFIG,
+            mock::NETIF,
+            mock::PIPELINES,
+            mock::RELAYS,
+            mock::REVISIONS,
+            mock::SENSORS,
+            mock::STATUS,
+        ])
+        .await
+    }
+
+    #[tokio::test]
+    async fn start_stop_current_state() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        assert_eq!(handler.stop().await.unwrap(), "Already stopped");
+
+        handler.bela_state.write().await.is_streaming = true;
+        assert_eq!(handler.start().await.unwrap(), "Already streaming");
+    }
+
+    #[tokio::test]
+    async fn start_request() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        assert_eq!(handler.start().await.unwrap(), "Starting BELABOX");
+
+        let expected = r#"{"start":{"pipeline":"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c","delay":0,"max_br":5000,"srt_latency":2000,"bitrate_overlay":false,"asrc":"No audio","acodec":"opus","remote_key":"remote_key","relay_server":"1","relay_account":"1"}}"#;
+        assert_eq!(server.request().await, expected);
+    }
+
+    #[tokio::test]
+    async fn overlay_start_request() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        assert_eq!(handler.overlay().await.unwrap(), "Bitrate overlay enabled");
+        handler.start().await.unwrap();
+
+        let request = server.request().await;
+        assert!(request.contains(r#""bitrate_overlay":true"#));
+    }
+
+    #[tokio::test]
+    async fn latency_restarts_stream() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        handler.start().await.unwrap();
+        server.request().await;
+        tokio::time::timeout(
+            tokio::time::Duration::from_secs(5),
+            handler.wait_for_streaming(true),
+        )
+        .await
+        .unwrap();
+
+        let response = handler.latency(Some("3000")).await.unwrap();
+        assert_eq!(response, "Changed SRT latency to 3000 ms");
+
+        assert_eq!(server.request().await, r#"{"stop":0}"#);
+        assert!(server.request().await.contains(r#""srt_latency":3000"#));
+    }
+
+    #[tokio::test]
+    async fn relative_latency() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+        let current = handler
+            .bela_state
+            .read()
+            .await
+            .config
+            .as_ref()
+            .unwrap()
+            .srt_latency;
+
+        let response = handler.latency(Some("+500")).await.unwrap();
+        let expected = increment_by_step((current + 500).min(4000) as f64, 100.0);
+        assert_eq!(
+            response,
+            format!("Changed SRT latency to {} ms", expected).as_str()
+        );
+
+        let response = handler.latency(Some("-10000")).await.unwrap();
+        assert_eq!(response, "Changed SRT latency to 100 ms");
+
+        let response = handler.latency(Some("+abc")).await.unwrap();
+        assert_eq!(response, "Invalid number +abc given");
+    }
+
+    #[tokio::test]
+    async fn relative_audio_delay() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.audio_delay(Some("100")).await.unwrap();
+        assert_eq!(response, "Changed audio delay to 100 ms");
+
+        let response = handler.audio_delay(Some("+20")).await.unwrap();
+        assert_eq!(response, "Changed audio delay to 120 ms");
+
+        let response = handler.audio_delay(Some("-250")).await.unwrap();
+        assert_eq!(response, "Changed audio delay to -140 ms");
+
+        let response = handler.audio_delay(Some("-5000")).await.unwrap();
+        assert_eq!(response, "Changed audio delay to -2000 ms");
+    }
+
+    #[tokio::test]
+    async fn pipeline_restarts_in_order() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        handler.start().await.unwrap();
+        server.request().await;
+        tokio::time::timeout(
+            tokio::time::Duration::from_secs(5),
+            handler.wait_for_streaming(true),
+        )
+        .await
+        .unwrap();
+
+        let other = handler.clone();
+        let (first, second) = tokio::join!(handler.pipeline(["h264"]), other.pipeline(["h265"]));
+        assert_eq!(first.unwrap(), "Changed pipeline to h264_camlink_1080p");
+        assert_eq!(second.unwrap(), "Changed pipeline to h265_camlink_1080p");
+
+        assert_eq!(server.request().await, r#"{"stop":0}"#);
+        assert!(server.request().await.contains(mock::PIPELINE_H264));
+        assert_eq!(server.request().await, r#"{"stop":0}"#);
+        assert!(server.request().await.contains(mock::PIPELINE_H265));
+    }
+
+    #[tokio::test]
+    async fn current_pipeline() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.pipeline([]).await.unwrap();
+        assert_eq!(response, "Current pipeline is h265_camlink_1080p");
+
+        {
+            let mut state = handler.bela_state.write().await;
+            state.config.as_mut().unwrap().pipeline = "unknown".to_string();
+        }
+
+        let response = handler.pipeline([]).await.unwrap();
+        assert_eq!(response, "Current pipeline unknown");
+    }
+
+    #[tokio::test]
+    async fn relay() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.relay(Vec::new()).await.unwrap();
+        assert_eq!(response, "Current relay server is US East");
+
+        let response = handler.relay(vec!["list"]).await.unwrap();
+        assert_eq!(response, "Relay servers: 1) Asia, 2) EU, 3) US East");
+
+        let response = handler.relay(vec!["account", "list"]).await.unwrap();
+        assert_eq!(response, "Relay accounts: 1) main");
+
+        let response = handler.relay(vec!["4"]).await.unwrap();
+        assert_eq!(response, "Relay server 4 not found");
+
+        let response = handler.relay(vec!["2"]).await.unwrap();
+        assert_eq!(response, "Changed relay server to EU");
+        let relay_server = handler
+            .bela_state
+            .read()
+            .await
+            .config
+            .as_ref()
+            .unwrap()
+            .relay_server
+            .to_owned();
+        assert_eq!(relay_server, "2");
+    }
+
+    #[tokio::test]
+    async fn pipeline_info() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let expected =
+            "h265_camlink_1080p: audio source selection available, audio codec selection available";
+        assert_eq!(handler.pipeline_info([]).await.unwrap(), expected);
+
+        let response = handler.pipeline_info(["h264"]).await.unwrap();
+        assert!(response.reply.starts_with("h264_camlink_1080p: "));
+    }
+
+    #[tokio::test]
+    async fn aliases() {
+        let mut server = server().await;
+        let mut handler = handler(&mut server).await;
+        handler.pipeline_aliases =
+            HashMap::from([("cam".to_string(), mock::PIPELINE_H264.to_string())]);
+        handler.asrc_aliases = HashMap::from([("mic".to_string(), "USB audio".to_string())]);
+
+        let response = handler.pipeline(["Cam"]).await.unwrap();
+        assert_eq!(response, "Changed pipeline to h264_camlink_1080p");
+
+        let response = handler.audio_src(["mic"]).await.unwrap();
+        assert_eq!(response, "Changed audio to USB audio");
+    }
+
+    #[tokio::test]
+    async fn audio_src_no_close_match() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.audio_src(vec!["xio"]).await.unwrap();
+        assert_eq!(
+            response,
+            "No close match, did you mean: No audio, USB audio?"
+        );
+
+        let response = handler.audio_src(vec!["hdmi"]).await.unwrap();
+        assert_eq!(response, "Audio source not found");
+    }
+
+    #[tokio::test]
+    async fn audio_codec() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.audio_codec([]).await.unwrap();
+        assert_eq!(response, "Current audio codec is opus");
+
+        let response = handler.audio_codec(["mp3"]).await.unwrap();
+        assert_eq!(response, "Unknown audio codec mp3, use opus or aac");
+
+        let response = handler.audio_codec(["AAC", "128"]).await.unwrap();
+        assert_eq!(
+            response,
+            "Changed audio codec to aac, the audio bitrate of 128 can't be set in belaUI"
+        );
+
+        {
+            let mut state = handler.bela_state.write().await;
+            for pipeline in state.pipelines.as_mut().unwrap().values_mut() {
+                pipeline.acodec = false;
+            }
+        }
+
+        let response = handler.audio_codec(["opus"]).await.unwrap();
+        assert_eq!(
+            response,
+            "The current pipeline doesn't support changing the audio codec"
+        );
+    }
+
+    #[tokio::test]
+    async fn current_audio_src() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.audio_src([]).await.unwrap();
+        assert_eq!(response, "Current audio source is No audio");
+    }
+
+    #[tokio::test]
+    async fn ssh_password_reset() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.ssh_password().await.unwrap();
+        assert_eq!(response, "New SSH password: new_password");
+        assert_eq!(server.request().await, r#"{"command":"reset_ssh_pass"}"#);
+    }
+
+    #[tokio::test]
+    async fn wifi_disconnect() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.wifi_disconnect().await.unwrap();
+        assert_eq!(response, "Disconnected from WiFi Home");
+
+        let expected = format!(r#"{{"wifi":{{"disconnect":"{}"}}}}"#, mock::WIFI_UUID);
+        assert_eq!(server.request().await, expected);
+    }
+
+    #[tokio::test]
+    async fn wifi_forget() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler
+            .wifi_forget("nope".split_whitespace())
+            .await
+            .unwrap();
+        assert_eq!(response, "No saved network named nope");
+
+        let response = handler
+            .wifi_forget("home".split_whitespace())
+            .await
+            .unwrap();
+        assert_eq!(response, "Forgot WiFi Home");
+
+        let expected = format!(r#"{{"wifi":{{"forget":"{}"}}}}"#, mock::WIFI_UUID);
+        assert_eq!(server.request().await, expected);
+    }
+
+    #[tokio::test]
+    async fn wifi_only_uplink() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        {
+            let mut lock = handler.bela_state.write().await;
+            lock.is_streaming = true;
+            let netif = lock.netif.as_mut().unwrap();
+            let mut wlan = netif["eth0"].to_owned();
+            wlan.ip = "192.168.0.20".to_string();
+            netif.insert("wlan0".to_string(), wlan);
+            netif.get_mut("eth0").unwrap().enabled = false;
+            netif.get_mut("usb0").unwrap().enabled = false;
+        }
+
+        let response = handler.wifi_disconnect().await.unwrap();
+        assert_eq!(
+            response,
+            "Can't disconnect the only network while streaming"
+        );
+    }
+
+    #[tokio::test]
+    async fn start_without_config() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let config = handler.bela_state.write().await.config.take().unwrap();
+        let response = handler.start().await.unwrap();
+        assert!(response
+            .reply
+            .starts_with("Error the BELABOX config hasn't been received yet"));
+        assert_eq!(response.severity, Severity::Error);
+
+        handler.bela_state.write().await.config = Some(belabox::messages::Config {
+            pipeline: String::new(),
+            asrc: String::new(),
+            ..config
+        });
+        let response = handler.start().await.unwrap();
+        assert_eq!(
+            response,
+            "Error the BELABOX config is missing: pipeline, asrc, set it in belaUI first"
+        );
+    }
+
+    #[tokio::test]
+    async fn start_with_missing_asrc() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        if let Some(config) = &mut handler.bela_state.write().await.config {
+            config.asrc = "Cam Link 4k".to_string();
+        }
+
+        let response = handler.start().await.unwrap();
+        assert_eq!(
+            response,
+            "Error the audio source Cam Link 4k is not available, change it first"
+        );
+        assert!(handler.bela_state.read().await.start_requested.is_none());
+    }
+
+    #[tokio::test]
+    async fn cancel() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.cancel().await.unwrap();
+        assert_eq!(response, "No restart in progress");
+
+        handler.bela_state.write().await.restart = Some(tokio::time::Instant::now());
+        let response = handler.cancel().await.unwrap();
+        assert_eq!(response, "Restart cancelled; stream will not auto-start");
+        assert_eq!(handler.bela_state.read().await.restart, None);
+    }
+
+    #[tokio::test]
+    async fn reboot_timeout() {
+        let mut server = server().await;
+        let sink = Arc::new(TestSink::default());
+        let handler = CommandHandler {
+            sink: sink.clone(),
+            reboot_timeout: 0,
+            ..handler(&mut server).await
+        };
+
+        let requested = tokio::time::Instant::now();
+        handler.bela_state.write().await.restart = Some(requested);
+        handler.reboot_timed_out(requested).await;
+
+        assert_eq!(handler.bela_state.read().await.restart, None);
+        let messages = sink.0.lock().unwrap();
+        assert!(messages[0].starts_with("BB: Reboot appears to have failed"));
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let expected = "eth0: 4000 kbps, usb0: 1000 kbps, Total: 5000 kbps";
+        assert_eq!(handler.stats().await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn stats_stale() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let last_update = tokio::time::Instant::now() - tokio::time::Duration::from_secs(30);
+        handler.bela_state.write().await.last_netif_update = Some(last_update);
+
+        let response = handler.stats().await.unwrap();
+        assert!(response.reply.starts_with("(stale) eth0: 4000 kbps"));
+    }
+
+    #[tokio::test]
+    async fn interfaces() {
+        let mut server = server().await;
+        let handler = CommandHandler {
+            custom_interface_name: HashMap::from([
+                ("eth0".to_string(), "ETH".to_string()),
+                ("usb0".to_string(), "USB".to_string()),
+                ("192.168.42.2".to_string(), "Modem".to_string()),
+            ]),
+            ..handler(&mut server).await
+        };
+
+        let response = handler.interfaces().await.unwrap();
+        assert_eq!(
+            response,
+            "eth0 (192.168.1.10) = ETH by name, usb0 (192.168.42.2) = Modem by IP"
+        );
+    }
+
+    #[tokio::test]
+    async fn execute() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler
+            .execute(BotCommand::Bitrate, "99999".split_whitespace())
+            .await;
+        assert_eq!(
+            response,
+            "Invalid value: 99999, use a value between 500 - 12000"
+        );
+
+        let response = handler
+            .execute(BotCommand::Bitrate, "".split_whitespace())
+            .await;
+        assert_eq!(response, "No bitrate given");
+        assert_eq!(response.severity, Severity::Warning);
+
+        let response = handler
+            .execute(BotCommand::Bitrate, "4500".split_whitespace())
+            .await;
+        assert_eq!(
+            response,
+            CommandOutcome::info("Changed max bitrate to 4500 kbps")
+        );
+    }
+
+    #[tokio::test]
+    async fn updating() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+        handler.bela_state.write().await.updating = true;
+
+        for command in [BotCommand::Start, BotCommand::Restart, BotCommand::Poweroff] {
+            let response = handler.execute(command, "".split_whitespace()).await;
+            assert_eq!(response, "BB: System updating, please wait");
+        }
+
+        let response = handler
+            .execute(BotCommand::Start, "force".split_whitespace())
+            .await;
+        assert_eq!(response, "Starting BELABOX");
+    }
+
+    #[tokio::test]
+    async fn info() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.info().await.unwrap();
+        assert_eq!(
+            response,
+            "Online, not streaming, Pipeline: h265_camlink_1080p, Audio: No audio, \
+             Bitrate: 5000 kbps, Latency: 2000 ms, Image: 2024.03, belaUI: d4e5f6a, \
+             belacoder: b7c8d9e, srtla: f0a1b2c"
+        );
+    }
+
+    #[tokio::test]
+    async fn status() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.status().await.unwrap();
+        assert_eq!(response, "Streaming: no, SSH: off");
+
+        handler
+            .bela_state
+            .write()
+            .await
+            .ssh
+            .as_mut()
+            .unwrap()
+            .active = true;
+        let response = handler.status().await.unwrap();
+        assert_eq!(response, "Streaming: no, SSH: on (user)");
+    }
+
+    #[tokio::test]
+    async fn stats_block() {
+        let mut server = server().await;
+        let handler = CommandHandler {
+            stats_target: true,
+            ..handler(&mut server).await
+        };
+
+        let response = handler.stats_block().await.unwrap();
+        assert_eq!(
+            response,
+            "eth0: 4000 kbps\nusb0: 1000 kbps\nTotal: 5000 kbps (target 5000)"
+        );
+    }
+
+    #[tokio::test]
+    async fn stats_target() {
+        let mut server = server().await;
+        let handler = CommandHandler {
+            stats_target: true,
+            ..handler(&mut server).await
+        };
+
+        let response = handler.stats().await.unwrap();
+        assert!(response.reply.ends_with("Total: 5000 kbps (target 5000)"));
+    }
+
+    #[tokio::test]
+    async fn stats_temperature() {
+        let mut server = server().await;
+        let handler = CommandHandler {
+            stats_temperature: true,
+            ..handler(&mut server).await
+        };
+
+        let response = handler.stats().await.unwrap();
+        assert!(response.reply.ends_with(", Temp: 52.1 °C"));
+    }
+
+    #[tokio::test]
+    async fn monitor_stats_format() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let full = handler.stats().await.unwrap();
+        assert_eq!(handler.monitor_stats().await, full.reply);
+
+        let handler = CommandHandler {
+            monitor_stats_format: vec![StatsPart::Total, StatsPart::Temperature],
+            ..handler
+        };
+        assert_eq!(
+            handler.monitor_stats().await,
+            "Total: 5000 kbps, Temp: 52.1 °C"
+        );
+    }
+
+    #[tokio::test]
+    async fn auto_stats_toggle() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+        handler.bela_state.write().await.auto_stats_interval = 30;
+
+        let response = handler.auto_stats(None).await.unwrap();
+        assert_eq!(response, "Automatic stats enabled every 30 seconds");
+        assert!(handler.bela_state.read().await.auto_stats);
+
+        let response = handler.auto_stats(Some("OFF")).await.unwrap();
+        assert_eq!(response, "Automatic stats disabled");
+
+        let response = handler.auto_stats(Some("60")).await.unwrap();
+        assert_eq!(response, "Automatic stats enabled every 60 seconds");
+
+        let response = handler.auto_stats(Some("0")).await.unwrap();
+        assert_eq!(response, "Invalid value, use on, off or seconds");
+        assert_eq!(handler.bela_state.read().await.auto_stats_interval, 60);
+    }
+
+    #[tokio::test]
+    async fn ping() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        handler.bela_state.write().await.cloud_rtt = Some(tokio::time::Duration::from_millis(42));
+        assert_eq!(handler.ping().await.unwrap(), "Cloud latency: 42 ms");
+
+        handler
+            .bela_state
+            .read()
+            .await
+            .dropped_messages
+            .store(7, std::sync::atomic::Ordering::Relaxed);
+        assert_eq!(
+            handler.ping().await.unwrap(),
+            "Cloud latency: 42 ms, dropped messages: 7"
+        );
+
+        {
+            let mut lock = handler.bela_state.write().await;
+            lock.online = false;
+            lock.started = tokio::time::Instant::now() - tokio::time::Duration::from_secs(3720);
+        }
+        assert_eq!(
+            handler.ping().await.unwrap(),
+            "Pong, the BELABOX is offline, bot uptime: 1h 2m"
+        );
+    }
+
+    #[tokio::test]
+    async fn bot() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        handler.bela_state.write().await.started =
+            tokio::time::Instant::now() - tokio::time::Duration::from_secs(90000);
+        assert_eq!(
+            handler.bot().await.unwrap(),
+            format!("belabot v{}, uptime: 25h 0m", env!("CARGO_PKG_VERSION")).as_str()
+        );
+    }
+
+    struct FailingSink;
+
+    #[async_trait::async_trait]
+    impl MessageSink for FailingSink {
+        async fn send(&self, _message: String) -> Result<()> {
+            Err(Error::File(std::io::ErrorKind::NotFound.into()))
+        }
+    }
+
+    #[test]
+    fn truncate_reply() {
+        let message = "usb0: 2500 kbps, usb1: 1500 kbps, wlan0: 500 kbps";
+        assert_eq!(super::truncate_reply(message, 100), message);
+        assert_eq!(
+            super::truncate_reply(message, 40),
+            "usb0: 2500 kbps, usb1: 1500 …(+4 more)"
+        );
+        assert_eq!(super::truncate_reply(message, 5), "…(+9 more)");
+    }
+
+    #[test]
+    fn split_reply() {
+        let message = "usb0: 2500 kbps, usb1: 1500 kbps, wlan0: 500 kbps";
+        assert_eq!(
+            super::split_reply(message, 20, " "),
+            vec!["usb0: 2500 kbps,", "usb1: 1500 kbps,", "wlan0: 500 kbps"]
+        );
+        assert_eq!(
+            super::split_reply(message, 34, ", "),
+            vec!["usb0: 2500 kbps, usb1: 1500 kbps", "wlan0: 500 kbps"]
+        );
+        assert_eq!(
+            super::split_reply("Presets: a, a long name, b", 8, ", "),
+            vec!["Presets:", "a", "a long", "name, b"]
+        );
+        assert_eq!(
+            super::split_reply("abcdefg hi", 3, " "),
+            vec!["abc", "def", "g", "hi"]
+        );
+    }
+
+    #[tokio::test]
+    async fn long_replies() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+        let sink = Arc::new(TestSink::default());
+        let handler = CommandHandler {
+            sink: sink.clone(),
+            max_reply_length: 28,
+            ..handler
+        };
+
+        let message = "Total: 4000 kbps, usb0: 2500 kbps".to_string();
+        handler.send(message).await;
+        assert_eq!(sink.0.lock().unwrap()[0], "Total: 4000 kbps, …(+3 more)");
+
+        let list = "Presets: day, night, rain, snow".to_string();
+        assert_eq!(
+            handler.reply_parts(list, Some(", ")),
+            vec!["Presets: day, night, rain", "snow"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_alert() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        assert_eq!(
+            handler.test_alert().await.unwrap(),
+            "No Discord webhooks or logs configured"
+        );
+
+        let log = Arc::new(TestSink::default());
+        let handler = CommandHandler {
+            external_sinks: vec![
+                ("Discord alerts", log.clone()),
+                ("Command log file", Arc::new(FailingSink)),
+            ],
+            ..handler
+        };
+
+        let response = handler.test_alert().await.unwrap();
+        assert_eq!(
+            response,
+            "Discord alerts: ok, Command log file: failed (File error)"
+        );
+        assert_eq!(response.severity, Severity::Error);
+        assert_eq!(
+            log.0.lock().unwrap()[0],
+            "BB: Test alert for Discord alerts"
+        );
+    }
+
+    #[tokio::test]
+    async fn notification_timeout() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+        handler.bela_state.write().await.notification_window = 30;
+
+        assert_eq!(
+            handler.notification_timeout(None).await.unwrap(),
+            "Current notification timeout is 30 seconds"
+        );
+        assert_eq!(
+            handler.notification_timeout(Some("soon")).await.unwrap(),
+            "Invalid number soon given"
+        );
+        assert_eq!(
+            handler.notification_timeout(Some("120")).await.unwrap(),
+            "Changed the notification timeout to 120 seconds"
+        );
+        assert_eq!(handler.bela_state.read().await.notification_window, 120);
+
+        // Saved to the config the bot was started with
+        let path = std::env::temp_dir().join(format!(
+            "belabot-notification-timeout-{}.json",
+            std::process::id()
+        ));
+        let config = r#"{"belabox":{},"twitch":{"bot_username":"","bot_oauth":"","channel":"","admins":[]},"commands":{}}"#;
+        std::fs::write(&path, config).unwrap();
+
+        let handler = CommandHandler {
+            config_path: Some(path.clone()),
+            ..handler
+        };
+        handler.notification_timeout(Some("90")).await.unwrap();
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let saved = serde_json::from_str::<config::Settings>(&saved).unwrap();
+        assert_eq!(saved.belabox.monitor.notification_timeout, 90);
+    }
+
+    #[tokio::test]
+    async fn quiet() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.quiet(None).await.unwrap();
+        assert_eq!(response, "Announcements aren't muted");
+
+        let response = handler.quiet(Some("300")).await.unwrap();
+        assert_eq!(response, "Muted announcements for 300 seconds");
+        assert!(handler.bela_state.write().await.is_muted());
+
+        let response = handler.quiet(Some("off")).await.unwrap();
+        assert_eq!(response, "Announcements unmuted");
+        assert!(!handler.bela_state.write().await.is_muted());
+
+        let response = handler.quiet(Some(&u64::MAX.to_string())).await.unwrap();
+        assert_eq!(response, "Invalid value, use off or seconds");
+        assert!(!handler.bela_state.write().await.is_muted());
+    }
+
+    #[tokio::test]
+    async fn notifications() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        assert_eq!(handler.notifications().await.unwrap(), "No notifications");
+
+        {
+            let mut lock = handler.bela_state.write().await;
+            for (name, msg) in [
+                ("a", "First"),
+                ("b", "Second"),
+                ("b", "Second"),
+                ("c", "Third"),
+                ("d", "Fourth"),
+            ] {
+                lock.add_notification(belabox::messages::NotificationMessage {
+                    duration: 0,
+                    is_dismissable: true,
+                    is_persistent: false,
+                    msg: msg.to_string(),
+                    name: name.to_string(),
+                    kind: "warning".to_string(),
+                });
+            }
+            assert_eq!(lock.notifications.len(), 4);
+        }
+
+        assert_eq!(
+            handler.notifications().await.unwrap(),
+            "Notifications: Fourth (0s ago) | Third (0s ago) | Second (0s ago)"
+        );
+    }
+
+    #[test]
+    fn age() {
+        use tokio::time::Duration;
+
+        assert_eq!(format_age(Duration::from_secs(45)), "45s");
+        assert_eq!(format_age(Duration::from_secs(750)), "12m");
+        assert_eq!(format_age(Duration::from_secs(11000)), "3h");
+    }
+
+    #[tokio::test]
+    async fn sync_request() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        handler.sync().await.unwrap();
+
+        let expected = r#"{"remote":{"auth/key":{"key":"remote_key","version":6}}}"#;
+        assert_eq!(server.request().await, expected);
+    }
+
+    fn message(sender_name: &str) -> crate::twitch::HandleMessage {
+        crate::twitch::HandleMessage {
+            channel_name: "715209".to_string(),
+            sender_id: "1".to_string(),
+            sender_name: sender_name.to_string(),
+            broadcaster: false,
+            moderator: false,
+            vip: false,
+            subscriber: false,
+            message_id: "1".to_string(),
+            message: "!bbb 4000".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscriber_permission() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let viewer = message("viewer");
+        let founder = crate::twitch::HandleMessage {
+            subscriber: true,
+            ..message("founder")
+        };
+        let vip = crate::twitch::HandleMessage {
+            vip: true,
+            ..message("vip")
+        };
+
+        for (hm, allowed) in [(viewer, false), (founder.clone(), true), (vip, true)] {
+            assert_eq!(
+                handler
+                    .is_allowed_to_execute(&Permission::Subscriber, &hm)
+                    .await,
+                allowed
+            );
+        }
+
+        assert!(
+            !handler
+                .is_allowed_to_execute(&Permission::Vip, &founder)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn log_command() {
+        let mut server = server().await;
+        let log = Arc::new(TestSink::default());
+        let handler = CommandHandler {
+            command_log: vec![log.clone(), log.clone()],
+            ..handler(&mut server).await
+        };
+
+        handler
+            .log_command(&message("viewer"), "Changed max bitrate to 4000 kbps")
+            .await;
+
+        let messages = log.0.lock().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0],
+            "viewer used !bbb 4000: Changed max bitrate to 4000 kbps"
+        );
+    }
+
+    #[tokio::test]
+    async fn grant_moderator() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+        let hm = message("viewer");
+
+        assert!(
+            !handler
+                .is_allowed_to_execute(&Permission::Moderator, &hm)
+                .await
+        );
+
+        let minutes = u64::MAX.to_string();
+        let response = handler.grant(Some("viewer"), Some(&minutes)).await.unwrap();
+        assert_eq!(response, "Invalid number of minutes given");
+
+        let minutes = (u64::MAX / 60).to_string();
+        let response = handler.grant(Some("viewer"), Some(&minutes)).await.unwrap();
+        assert_eq!(response, "Invalid number of minutes given");
+
+        let response = handler.grant(Some("@Viewer"), Some("5")).await.unwrap();
+        assert_eq!(response, "viewer has moderator access for 5 minutes");
+        assert!(
+            handler
+                .is_allowed_to_execute(&Permission::Moderator, &hm)
+                .await
+        );
+        assert!(
+            !handler
+                .is_allowed_to_execute(&Permission::Broadcaster, &hm)
+                .await
+        );
+
+        handler.grant(Some("viewer"), Some("0")).await.unwrap();
+        assert!(
+            !handler
+                .is_allowed_to_execute(&Permission::Moderator, &hm)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_grant() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let expired = tokio::time::Instant::now() - tokio::time::Duration::from_secs(1);
+        handler
+            .grants
+            .write()
+            .await
+            .insert("viewer".to_string(), expired);
+
+        let hm = message("viewer");
+        assert!(
+            !handler
+                .is_allowed_to_execute(&Permission::Moderator, &hm)
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn offline_reply_cooldown() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        assert!(handler.offline_reply_allowed().await);
+        assert!(!handler.offline_reply_allowed().await);
+
+        let handler = CommandHandler {
+            offline_message: String::new(),
+            last_offline_reply: Default::default(),
+            ..handler
+        };
+        assert!(!handler.offline_reply_allowed().await);
+    }
+
+    #[tokio::test]
+    async fn command_cooldown() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let info = config::CommandInformation {
+            command: "!bbs".to_string(),
+            permission: Permission::Public,
+            whisper: false,
+            cooldown: 5,
+            cooldown_reply: true,
+            channels: HashMap::new(),
+            requires_online: None,
+        };
+
+        assert_eq!(
+            handler.cooldown_remaining(&BotCommand::Stats, &info).await,
+            None
+        );
+        assert_eq!(
+            handler.cooldown_remaining(&BotCommand::Stats, &info).await,
+            Some(5)
+        );
+        assert_eq!(
+            handler.cooldown_remaining(&BotCommand::Sensor, &info).await,
+            None
+        );
+
+        let info = config::CommandInformation {
+            cooldown: 0,
+            ..info
+        };
+        assert_eq!(
+            handler.cooldown_remaining(&BotCommand::Stats, &info).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn command_exact_match() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+        config::default_chat_commands(&mut *handler.commands.write().await);
+
+        let handler = &handler;
+        let command = |c| async move { handler.command(c).await.map(|(command, _)| command) };
+
+        assert_eq!(command("!bbs").await, Some(BotCommand::Stats));
+        assert_eq!(command("!BBS").await, Some(BotCommand::Stats));
+        assert_eq!(command("!bbsensor").await, Some(BotCommand::Sensor));
+        assert_eq!(command("!bbsens").await, None);
+        assert_eq!(command("!bbs2").await, None);
+        assert_eq!(command("bbs").await, None);
+
+        // Configured triggers match regardless of case as well
+        if let Some(info) = handler.commands.write().await.get_mut(&BotCommand::Ping) {
+            info.command = "!BBPing".to_string();
+        }
+        assert_eq!(command("!bbping").await, Some(BotCommand::Ping));
+    }
+
+    #[tokio::test]
+    async fn permission_validation() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+        config::default_chat_commands(&mut *handler.commands.write().await);
+
+        let response = handler.permission(["!bbs", "admin"]).await.unwrap();
+        assert_eq!(
+            response,
+            "Unknown permission admin, use Broadcaster, Moderator, Vip, Subscriber or Public"
+        );
+
+        let response = handler.permission(["!bbnope", "vip"]).await.unwrap();
+        assert_eq!(response, "Unknown command !bbnope");
+
+        let response = handler.permission(["!bbs"]).await.unwrap();
+        assert!(response.reply.starts_with("Usage: "));
+
+        let commands = handler.commands.read().await;
+        assert_eq!(commands[&BotCommand::Stats].permission, Permission::Public);
+    }
+
+    #[tokio::test]
+    async fn bitrate_request() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let response = handler.bitrate(Some("4600")).await.unwrap();
+        assert_eq!(response, "Changed max bitrate to 4500 kbps");
+
+        let expected = r#"{"bitrate":{"max_br":4500}}"#;
+        assert_eq!(server.request().await, expected);
+    }
+
+    #[tokio::test]
+    async fn load_preset_start_request() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        let preset = {
+            let state = handler.bela_state.read().await;
+            config::Preset {
+                max_br: 3000,
+                srt_latency: 1500,
+                asrc: "USB audio".to_string(),
+                ..config::Preset::from(state.config.as_ref().unwrap())
+            }
+        };
+        handler
+            .presets
+            .write()
+            .await
+            .insert("irl".to_string(), preset);
+
+        let response = handler.preset(["load", "IRL"]).await.unwrap();
+        assert_eq!(response, "Loaded preset irl");
+        handler.start().await.unwrap();
+
+        let request = server.request().await;
+        assert!(request.contains(r#""max_br":3000,"srt_latency":1500"#));
+        assert!(request.contains(r#""asrc":"USB audio""#));
+    }
+
+    #[tokio::test]
+    async fn load_preset_missing_asrc_while_streaming() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        handler.start().await.unwrap();
+        server.request().await;
+        tokio::time::timeout(
+            tokio::time::Duration::from_secs(5),
+            handler.wait_for_streaming(true),
+        )
+        .await
+        .unwrap();
+
+        let preset = {
+            let state = handler.bela_state.read().await;
+            config::Preset {
+                asrc: "Cam Link 4k".to_string(),
+                ..config::Preset::from(state.config.as_ref().unwrap())
+            }
+        };
+        handler
+            .presets
+            .write()
+            .await
+            .insert("irl".to_string(), preset);
+
+        let response = handler.preset(["load", "irl"]).await.unwrap();
+        assert_eq!(
+            response,
+            "Error the audio source Cam Link 4k is not available, change it first"
+        );
+        assert_eq!(response.severity, Severity::Error);
+        assert_eq!(server.request().await, r#"{"stop":0}"#);
+    }
+
+    #[tokio::test]
+    async fn defaults_start_request() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        handler.latency(Some("3000")).await.unwrap();
+        handler.defaults().await.unwrap();
+        handler.start().await.unwrap();
+
+        let request = server.request().await;
+        assert!(request.contains(r#""delay":0,"max_br":5000,"srt_latency":100"#));
+    }
+
+    #[tokio::test]
+    async fn list_presets() {
+        let mut server = server().await;
+        let handler = handler(&mut server).await;
+
+        assert_eq!(handler.preset([]).await.unwrap(), "No presets saved");
+        assert_eq!(
+            handler.preset(["load", "missing"]).await.unwrap(),
+            "Preset missing not found"
+        );
+    }
+
+    fn netif(ip: &str) -> belabox::messages::Netif {
+        belabox::messages::Netif {
+            ip: ip.to_string(),
+            txb: None,
+            tp: 0,
+            enabled: true,
+            error: None,
+        }
+    }
+
+    fn netifs() -> HashMap<String, belabox::messages::Netif> {
+        let mut netifs = HashMap::new();
+        netifs.insert("eth0".to_string(), netif("192.168.1.10"));
+        netifs.insert("usb0".to_string(), netif("192.168.42.2"));
+        netifs
+    }
+
+    #[test]
+    fn find_interface_by_name() {
+        let netifs = netifs();
+        let custom = HashMap::new();
+
+        let (name, _) = find_interface("ETH0", &netifs, &custom).unwrap();
+        assert_eq!(name, "eth0");
+    }
+
+    #[test]
+    fn find_interface_by_custom_name() {
+        let netifs = netifs();
+        let mut custom = HashMap::new();
+        custom.insert("usb0".to_string(), "Phone".to_string());
+
+        let (name, _) = find_interface("phone", &netifs, &custom).unwrap();
+        assert_eq!(name, "usb0");
+    }
+
+    #[test]
+    fn find_interface_by_custom_name_ip() {
+        let netifs = netifs();
+        let mut custom = HashMap::new();
+        custom.insert("192.168.42.2".to_string(), "Modem".to_string());
+
+        let (name, interface) = find_interface("MODEM", &netifs, &custom).unwrap();
+        assert_eq!(name, "usb0");
+        assert_eq!(interface.ip, "192.168.42.2");
+    }
+
+    #[test]
+    fn interface_name_ip_first() {
+        let netifs = netifs();
+        let usb0 = &netifs["usb0"];
+        let mut custom = HashMap::new();
+
+        assert_eq!(interface_name("usb0", usb0, &custom), "usb0");
+
+        custom.insert("usb0".to_string(), "Phone".to_string());
+        assert_eq!(interface_name("usb0", usb0, &custom), "Phone");
+
+        custom.insert("192.168.42.2".to_string(), "Modem".to_string());
+        assert_eq!(interface_name("usb0", usb0, &custom), "Modem");
+    }
+
+    #[test]
+    fn find_interface_by_number() {
+        let netifs = netifs();
+        let custom = HashMap::new();
+
+        let (name, _) = find_interface("2", &netifs, &custom).unwrap();
+        assert_eq!(name, "usb0");
+        assert!(find_interface("0", &netifs, &custom).is_none());
+        assert!(find_interface("3", &netifs, &custom).is_none());
+    }
+
+    #[test]
+    fn find_interface_not_found() {
+        let netifs = netifs();
+        let mut custom = HashMap::new();
+        custom.insert("192.168.1.99".to_string(), "Modem".to_string());
+
+        assert!(find_interface("modem", &netifs, &custom).is_none());
+        assert!(find_interface("wlan0", &netifs, &custom).is_none());
+    }
+}
@@ -1,9 +1,12 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use read_input::prelude::*;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tracing::error;
+use tracing::{error, warn};
 
 const CONFIG_FILE_NAME: &str = "config.json";
 
@@ -13,6 +16,13 @@ pub enum ConfigError {
     Io(#[from] std::io::Error),
     #[error("Json error: {0}")]
     Json(#[from] serde_json::error::Error),
+    #[error("The {0:?} command can't be empty or contain spaces")]
+    InvalidCommand(BotCommand),
+    #[error("The command {command} is used by multiple commands: {bot_commands:?}")]
+    DuplicateCommand {
+        command: String,
+        bot_commands: Vec<BotCommand>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -20,14 +30,99 @@ pub struct Settings {
     pub belabox: Belabox,
     pub twitch: Twitch,
     pub commands: HashMap<BotCommand, CommandInformation>,
+    #[serde(default)]
+    pub discord: Discord,
+    #[serde(default)]
+    pub presets: HashMap<String, Preset>,
+    #[serde(default)]
+    pub http: Http,
+    /// Log the requests instead of sending them to the BELABOX
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Seconds between heartbeat logs, disabled when 0
+    #[serde(default)]
+    pub heartbeat: u64,
+    #[serde(default)]
+    pub reconnect: Reconnect,
+    #[serde(default)]
+    pub command_log: CommandLog,
+    /// Where the config was loaded from, changes made in chat are saved there
+    #[serde(skip)]
+    pub path: PathBuf,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(default)]
 pub struct Belabox {
     pub remote_key: String,
     pub custom_interface_name: HashMap<String, String>,
+    /// Names that select a pipeline id without fuzzy matching
+    pub pipeline_aliases: HashMap<String, String>,
+    /// Names that select an audio source without fuzzy matching
+    pub asrc_aliases: HashMap<String, String>,
     pub monitor: Monitor,
+    pub ws_url: String,
+    /// Prefix interfaces with their number in the stats
+    pub number_interfaces: bool,
+    /// Add the SoC temperature to the stats
+    pub stats_temperature: bool,
+    /// Add the max bitrate next to the total in the stats
+    pub stats_target: bool,
+    /// What the automatic stats contain, the same as the stats command when
+    /// empty
+    pub monitor_stats_format: Vec<StatsPart>,
+    pub defaults: Defaults,
+    /// Max seconds to wait for the stream to stop before starting it again
+    pub restart_delay: u64,
+    /// Min similarity of a pipeline or audio source name to the query
+    pub match_threshold: f64,
+    /// Max seconds to wait for the BELABOX to come back after a reboot
+    pub reboot_timeout: u64,
+    /// The reply to commands while the BELABOX is offline
+    pub offline_message: String,
+    pub offline_cooldown: u64,
+}
+
+impl Default for Belabox {
+    fn default() -> Self {
+        Self {
+            remote_key: Default::default(),
+            custom_interface_name: Default::default(),
+            pipeline_aliases: Default::default(),
+            asrc_aliases: Default::default(),
+            monitor: Default::default(),
+            ws_url: crate::belabox::BELABOX_WS.to_string(),
+            number_interfaces: false,
+            stats_temperature: false,
+            stats_target: false,
+            monitor_stats_format: Vec::new(),
+            defaults: Default::default(),
+            restart_delay: 5,
+            match_threshold: 0.3,
+            reboot_timeout: 300,
+            offline_message: "Offline :(".to_string(),
+            offline_cooldown: 30,
+        }
+    }
+}
+
+/// The values the defaults command resets the stream settings to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Defaults {
+    pub bitrate: u32,
+    pub latency: u64,
+    pub delay: i32,
+}
+
+impl Default for Defaults {
+    fn default() -> Self {
+        Self {
+            bitrate: 5000,
+            latency: 100,
+            delay: 0,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -37,9 +132,28 @@ pub struct Monitor {
     pub notifications: bool,
     pub ups: bool,
     pub network: bool,
+    pub temperature: bool,
     pub ups_plugged_in: f64,
+    pub temperature_threshold: f64,
     pub notification_timeout: u64,
     pub network_timeout: u64,
+    /// Seconds to collect interface changes before announcing them
+    pub modems_window: u64,
+    /// Start the stream again when it stops without a stop command
+    pub auto_restart: bool,
+    pub auto_restart_delay: u64,
+    /// Announce stream settings changed outside of the bot
+    pub config: bool,
+    /// Announce when updates become available
+    pub updates: bool,
+    /// Announce when the measured bitrate stays far below the max bitrate
+    pub target: bool,
+    /// The fraction of the max bitrate the measured bitrate has to stay below
+    pub target_ratio: f64,
+    /// Seconds the measured bitrate has to stay below the target
+    pub target_duration: u64,
+    /// Replacements for the default alert messages by their key
+    pub messages: HashMap<String, String>,
 }
 
 impl Default for Monitor {
@@ -49,58 +163,401 @@ impl Default for Monitor {
             notifications: true,
             ups: false,
             network: false,
+            temperature: false,
             ups_plugged_in: 5.1,
+            temperature_threshold: 80.0,
             notification_timeout: 30,
             network_timeout: 30,
+            modems_window: 3,
+            auto_restart: false,
+            auto_restart_delay: 10,
+            config: false,
+            updates: false,
+            target: false,
+            target_ratio: 0.6,
+            target_duration: 30,
+            messages: Default::default(),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Twitch {
     pub bot_username: String,
     pub bot_oauth: String,
     pub channel: String,
     pub admins: Vec<String>,
+    /// Used together with the refresh token to refresh the bot oauth
+    #[serde(default)]
+    pub client_id: Option<String>,
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Default minutes a grant of temporary moderator permissions lasts
+    #[serde(default = "default_grant_duration")]
+    pub grant_duration: u64,
+    /// Post the monitor announcements in this channel instead
+    #[serde(default)]
+    pub monitor_channel: String,
+    /// Send the monitor announcements as `/me` actions
+    #[serde(default)]
+    pub action_announcements: bool,
+    /// Send the command replies as `/me` actions
+    #[serde(default)]
+    pub action_replies: bool,
+    /// Seconds in which a message identical to the previous one isn't sent
+    /// again, 0 to always send it
+    #[serde(default = "default_duplicate_window")]
+    pub duplicate_window: u64,
+    /// Longer replies are truncated or split, 0 to send them as is
+    #[serde(default = "default_max_reply_length")]
+    pub max_reply_length: usize,
+    /// Split longer replies into multiple messages instead of truncating them
+    #[serde(default)]
+    pub split_replies: bool,
+}
+
+fn default_grant_duration() -> u64 {
+    60
+}
+
+fn default_duplicate_window() -> u64 {
+    30
+}
+
+fn default_max_reply_length() -> usize {
+    500
+}
+
+impl Default for Twitch {
+    fn default() -> Self {
+        Self {
+            bot_username: Default::default(),
+            bot_oauth: Default::default(),
+            channel: Default::default(),
+            admins: Default::default(),
+            client_id: Default::default(),
+            client_secret: Default::default(),
+            refresh_token: Default::default(),
+            grant_duration: default_grant_duration(),
+            monitor_channel: Default::default(),
+            action_announcements: Default::default(),
+            action_replies: Default::default(),
+            duplicate_window: default_duplicate_window(),
+            max_reply_length: default_max_reply_length(),
+            split_replies: Default::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Discord {
+    pub webhook: String,
+    pub alerts: HashMap<Alert, AlertRoute>,
+    pub stats_webhook: String,
+    pub stats_interval: u64,
+    /// Post the stats with a line per interface instead of a single line
+    pub stats_block: bool,
+}
+
+impl Default for Discord {
+    fn default() -> Self {
+        Self {
+            webhook: Default::default(),
+            alerts: Default::default(),
+            stats_webhook: Default::default(),
+            stats_interval: 60,
+            stats_block: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Alert {
+    Config,
+    Modems,
+    Network,
+    Notifications,
+    Restart,
+    Target,
+    Temperature,
+    Updates,
+    Ups,
+}
+
+/// Where a monitor alert should be posted, alerts without a route are
+/// posted in chat.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AlertRoute {
+    #[default]
+    Chat,
+    Discord,
+    Both,
+}
+
+/// How long to wait between reconnects, the delay doubles after every failed
+/// attempt up to the max.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Reconnect {
+    /// Seconds to wait before the first reconnect
+    pub min_delay: u64,
+    /// Max seconds to wait between reconnects
+    pub max_delay: u64,
+    /// The fraction of the delay which is randomly added or removed, so
+    /// reconnects don't all happen at once
+    pub jitter: f64,
+    /// Times a request to the BELABOX is retried when it can't be sent
+    pub retries: u32,
+    /// Milliseconds to wait before retrying a request
+    pub retry_delay: u64,
+    /// Max seconds a request waits for the reconnect while disconnected, 0
+    /// to fail right away
+    pub queue_timeout: u64,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            min_delay: 2,
+            max_delay: 32,
+            jitter: 0.2,
+            retries: 3,
+            retry_delay: 500,
+            queue_timeout: 0,
+        }
+    }
+}
+
+/// Where every used command and its result is logged, each is disabled when
+/// empty.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct CommandLog {
+    /// Appends a line to this file
+    pub file: String,
+    pub discord_webhook: String,
+    pub twitch_channel: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Http {
+    /// The address to serve the HTTP endpoints on, disabled when empty
+    pub address: String,
+    /// Bearer token required to run commands, disabled when empty
+    pub token: String,
+}
+
+/// The stream settings which can be saved and loaded by name.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Preset {
+    pub pipeline: String,
+    pub asrc: String,
+    pub acodec: String,
+    pub max_br: u32,
+    pub delay: i32,
+    pub srt_latency: u64,
+    pub relay_server: String,
+    pub relay_account: String,
+}
+
+impl From<&crate::belabox::messages::Config> for Preset {
+    fn from(c: &crate::belabox::messages::Config) -> Self {
+        Self {
+            pipeline: c.pipeline.to_owned(),
+            asrc: c.asrc.to_owned(),
+            acodec: c.acodec.to_owned(),
+            max_br: c.max_br,
+            delay: c.delay,
+            srt_latency: c.srt_latency,
+            relay_server: c.relay_server.to_owned(),
+            relay_account: c.relay_account.to_owned(),
+        }
+    }
+}
+
+impl Preset {
+    /// Applies the preset to the BELABOX config.
+    pub fn apply(&self, config: &mut crate::belabox::messages::Config) {
+        config.pipeline = self.pipeline.to_owned();
+        config.asrc = self.asrc.to_owned();
+        config.acodec = self.acodec.to_owned();
+        config.max_br = self.max_br;
+        config.delay = self.delay;
+        config.srt_latency = self.srt_latency;
+        config.relay_server = self.relay_server.to_owned();
+        config.relay_account = self.relay_account.to_owned();
+    }
+
+    /// Applies only the settings which differ from the given base to the
+    /// BELABOX config.
+    pub fn apply_changes(&self, base: &Preset, config: &mut crate::belabox::messages::Config) {
+        if self.pipeline != base.pipeline {
+            config.pipeline = self.pipeline.to_owned();
+        }
+        if self.asrc != base.asrc {
+            config.asrc = self.asrc.to_owned();
+        }
+        if self.acodec != base.acodec {
+            config.acodec = self.acodec.to_owned();
+        }
+        if self.max_br != base.max_br {
+            config.max_br = self.max_br;
+        }
+        if self.delay != base.delay {
+            config.delay = self.delay;
+        }
+        if self.srt_latency != base.srt_latency {
+            config.srt_latency = self.srt_latency;
+        }
+        if self.relay_server != base.relay_server {
+            config.relay_server = self.relay_server.to_owned();
+        }
+        if self.relay_account != base.relay_account {
+            config.relay_account = self.relay_account.to_owned();
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CommandInformation {
     pub command: String,
     pub permission: Permission,
+    /// Send the response as a whisper to the user instead of in chat
+    #[serde(default)]
+    pub whisper: bool,
+    /// Min seconds between uses of this command
+    #[serde(default)]
+    pub cooldown: u64,
+    /// Tell the user how long the command is still on cooldown
+    #[serde(default)]
+    pub cooldown_reply: bool,
+    /// Overrides the permission per channel, `None` disables the command in
+    /// that channel
+    #[serde(default)]
+    pub channels: HashMap<String, Option<Permission>>,
+    /// Reply with the offline message while the BELABOX is offline instead
+    /// of running the command, by default only commands which don't need
+    /// the BELABOX run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requires_online: Option<bool>,
+}
+
+impl CommandInformation {
+    /// The permission needed in the channel, `None` when the command is
+    /// disabled there.
+    pub fn permission_in(&self, channel: &str) -> Option<&Permission> {
+        self.channels
+            .iter()
+            .find(|(c, _)| c.eq_ignore_ascii_case(channel))
+            .map_or(Some(&self.permission), |(_, p)| p.as_ref())
+    }
+
+    pub fn requires_online(&self, command: &BotCommand) -> bool {
+        self.requires_online
+            .unwrap_or_else(|| !command.works_offline())
+    }
+}
+
+/// A part of the stats.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsPart {
+    Interfaces,
+    Total,
+    Ups,
+    Temperature,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
 pub enum BotCommand {
+    AudioCodec,
     AudioDelay,
     AudioSrc,
+    AutoStats,
     Bitrate,
+    Bot,
+    Cancel,
+    Defaults,
+    Grant,
+    Info,
+    Interfaces,
     Latency,
     Network,
+    Notifications,
+    NotificationTimeout,
+    Overlay,
+    Permission,
+    Ping,
     Pipeline,
+    PipelineInfo,
     Poweroff,
+    Preset,
+    Quiet,
+    Relay,
     Restart,
     Sensor,
+    SshPassword,
     Start,
     Stats,
+    Status,
     Stop,
+    Sync,
+    TestAlert,
+    WifiDisconnect,
+    WifiForget,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl BotCommand {
+    /// The BELABOX is offline while rebooting, when cancelling matters, the
+    /// info shows the last known state to debug problems and ping and bot
+    /// show that the bot itself works. The others are the default for
+    /// `requires_online`.
+    pub fn works_offline(&self) -> bool {
+        matches!(
+            self,
+            BotCommand::Bot
+                | BotCommand::Cancel
+                | BotCommand::Info
+                | BotCommand::Ping
+                | BotCommand::TestAlert
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Permission {
     Broadcaster,
     Moderator,
     Vip,
+    Subscriber,
     Public,
 }
 
+impl Permission {
+    /// Finds the permission by its name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Permission::Broadcaster,
+            Permission::Moderator,
+            Permission::Vip,
+            Permission::Subscriber,
+            Permission::Public,
+        ]
+        .into_iter()
+        .find(|p| format!("{:?}", p).eq_ignore_ascii_case(name))
+    }
+}
+
 impl Settings {
     /// Loads the config
     pub fn load<P>(path: P) -> Result<Self, ConfigError>
     where
         P: AsRef<std::path::Path>,
     {
-        let file = std::fs::read_to_string(path)?;
+        let file = std::fs::read_to_string(&path)?;
         let mut config = match serde_json::from_str::<Settings>(&file) {
             Ok(c) => c,
             Err(e) => {
@@ -109,6 +566,8 @@ impl Settings {
             }
         };
 
+        config.path = path.as_ref().to_owned();
+
         // Lowercase important settings such as the twitch channel name to
         // avoid issues.
         lowercase_settings(&mut config);
@@ -116,11 +575,46 @@ impl Settings {
         // Insert chat commands in the config if they don't exist.
         default_chat_commands(&mut config.commands);
 
-        std::fs::write(CONFIG_FILE_NAME, serde_json::to_string_pretty(&config)?)?;
+        if let Err(e) = validate_commands(&config.commands) {
+            error!(%e, "config error");
+            return Err(e);
+        }
+
+        // Rewriting the config is best-effort, the parsed config is still
+        // usable when the file can't be written (read-only fs, permissions).
+        if let Err(e) = config.save(&path) {
+            warn!(
+                ?e,
+                "failed to rewrite config, continuing with the loaded config"
+            );
+        }
 
         Ok(config)
     }
 
+    /// Applies a change to the config file at the path
+    pub fn update_file<F>(path: &Path, f: F) -> Result<(), ConfigError>
+    where
+        F: FnOnce(&mut Settings),
+    {
+        let file = std::fs::read_to_string(path)?;
+        let mut config = serde_json::from_str::<Settings>(&file)?;
+
+        f(&mut config);
+
+        config.save(path)
+    }
+
+    /// Saves the config
+    pub fn save<P>(&self, path: P) -> Result<(), ConfigError>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+
+        Ok(())
+    }
+
     pub async fn ask_for_settings() -> Result<Self, ConfigError> {
         println!("Please paste your BELABOX Cloud remote URL below");
 
@@ -184,6 +678,7 @@ impl Settings {
             remote_key,
             custom_interface_name,
             monitor,
+            ..Default::default()
         };
 
         println!("\nPlease enter your Twitch details below");
@@ -194,6 +689,7 @@ impl Settings {
                 .get(),
             channel: input().msg("Channel name: ").get(),
             admins: Vec::new(),
+            ..Default::default()
         };
 
         let admins = input::<String>()
@@ -213,12 +709,19 @@ impl Settings {
             belabox,
             twitch,
             commands,
+            discord: Discord::default(),
+            presets: HashMap::new(),
+            http: Http::default(),
+            dry_run: false,
+            heartbeat: 0,
+            reconnect: Default::default(),
+            command_log: Default::default(),
+            path: PathBuf::from(CONFIG_FILE_NAME),
         };
 
-        std::fs::write(CONFIG_FILE_NAME, serde_json::to_string_pretty(&settings)?)?;
+        settings.save(CONFIG_FILE_NAME)?;
 
-        // FIXME: Does not work on windows
-        print!("\x1B[2J");
+        clear_screen();
 
         let mut path = std::env::current_dir()?;
         path.push(CONFIG_FILE_NAME);
@@ -241,10 +744,12 @@ fn lowercase_settings(settings: &mut Settings) {
         bot_oauth,
         channel,
         admins,
+        monitor_channel,
         ..
     } = &mut settings.twitch;
 
     *channel = channel.to_lowercase();
+    *monitor_channel = monitor_channel.to_lowercase();
     *bot_oauth = bot_oauth.to_lowercase();
     *bot_username = bot_username.to_lowercase();
 
@@ -257,94 +762,252 @@ fn lowercase_settings(settings: &mut Settings) {
     }
 }
 
+/// Chat messages are split on whitespace to find the command, a command
+/// that is empty or contains whitespace would never match. Every command
+/// also has to be unique ignoring case, otherwise it's random which one runs.
+fn validate_commands(
+    commands: &HashMap<BotCommand, CommandInformation>,
+) -> Result<(), ConfigError> {
+    let invalid = commands
+        .iter()
+        .find(|(_, info)| info.command.is_empty() || info.command.contains(char::is_whitespace));
+
+    if let Some((command, _)) = invalid {
+        return Err(ConfigError::InvalidCommand(command.to_owned()));
+    }
+
+    let mut triggers: HashMap<String, Vec<BotCommand>> = HashMap::new();
+    for (command, info) in commands {
+        triggers
+            .entry(info.command.to_lowercase())
+            .or_default()
+            .push(command.to_owned());
+    }
+
+    let mut duplicates = triggers
+        .into_iter()
+        .filter(|(_, bot_commands)| bot_commands.len() > 1)
+        .collect::<Vec<_>>();
+    duplicates.sort_by(|a, b| a.0.cmp(&b.0));
+
+    if let Some((command, mut bot_commands)) = duplicates.into_iter().next() {
+        bot_commands.sort_by_key(|c| format!("{:?}", c));
+
+        return Err(ConfigError::DuplicateCommand {
+            command,
+            bot_commands,
+        });
+    }
+
+    Ok(())
+}
+
+/// Clears the terminal, the Windows console doesn't always support ANSI
+/// escape codes so use cls instead.
+fn clear_screen() {
+    if cfg!(windows) {
+        let _ = std::process::Command::new("cmd")
+            .args(["/C", "cls"])
+            .status();
+    } else {
+        print!("\x1B[2J\x1B[1;1H");
+    }
+}
+
 /// Converts y or n to bool.
 fn input_to_bool(confirm: String) -> bool {
     confirm.to_lowercase() == "y"
 }
 
 // Insert default commands if they don't exist
-fn default_chat_commands(commands: &mut HashMap<BotCommand, CommandInformation>) {
-    commands
-        .entry(BotCommand::Start)
-        .or_insert(CommandInformation {
-            command: "!bbstart".to_string(),
-            permission: Permission::Broadcaster,
-        });
+pub(crate) fn default_chat_commands(commands: &mut HashMap<BotCommand, CommandInformation>) {
+    let defaults = [
+        (BotCommand::Start, "!bbstart", Permission::Broadcaster),
+        (BotCommand::Stop, "!bbstop", Permission::Broadcaster),
+        (BotCommand::Stats, "!bbs", Permission::Public),
+        (BotCommand::Restart, "!bbrs", Permission::Broadcaster),
+        (BotCommand::Cancel, "!bbcancel", Permission::Broadcaster),
+        (BotCommand::Poweroff, "!bbpo", Permission::Broadcaster),
+        (BotCommand::Bitrate, "!bbb", Permission::Broadcaster),
+        (BotCommand::Sensor, "!bbsensor", Permission::Public),
+        (BotCommand::Network, "!bbt", Permission::Broadcaster),
+        (BotCommand::Latency, "!bbl", Permission::Broadcaster),
+        (BotCommand::AudioDelay, "!bbd", Permission::Broadcaster),
+        (BotCommand::Pipeline, "!bbp", Permission::Broadcaster),
+        (BotCommand::PipelineInfo, "!bbpi", Permission::Public),
+        (BotCommand::AudioSrc, "!bba", Permission::Broadcaster),
+        (BotCommand::AudioCodec, "!bbacodec", Permission::Broadcaster),
+        (BotCommand::Overlay, "!bbo", Permission::Broadcaster),
+        (BotCommand::Preset, "!bbpreset", Permission::Broadcaster),
+        (BotCommand::Defaults, "!bbdefaults", Permission::Broadcaster),
+        (BotCommand::SshPassword, "!bbssh", Permission::Broadcaster),
+        (BotCommand::Sync, "!bbsync", Permission::Broadcaster),
+        (BotCommand::Grant, "!bbgrant", Permission::Broadcaster),
+        (BotCommand::Relay, "!bbrelay", Permission::Broadcaster),
+        (BotCommand::Notifications, "!bbnotif", Permission::Moderator),
+        (BotCommand::Quiet, "!bbquiet", Permission::Moderator),
+        (
+            BotCommand::NotificationTimeout,
+            "!bbnotiftimeout",
+            Permission::Broadcaster,
+        ),
+        (BotCommand::Ping, "!bbping", Permission::Public),
+        (BotCommand::Status, "!bbstatus", Permission::Moderator),
+        (BotCommand::Info, "!bbinfo", Permission::Moderator),
+        (BotCommand::Bot, "!bbbot", Permission::Moderator),
+        (
+            BotCommand::Interfaces,
+            "!bbinterfaces",
+            Permission::Broadcaster,
+        ),
+        (BotCommand::Permission, "!bbperm", Permission::Broadcaster),
+        (
+            BotCommand::TestAlert,
+            "!bbtestalert",
+            Permission::Broadcaster,
+        ),
+        (
+            BotCommand::AutoStats,
+            "!bbautostats",
+            Permission::Broadcaster,
+        ),
+        (
+            BotCommand::WifiDisconnect,
+            "!bbwifioff",
+            Permission::Broadcaster,
+        ),
+        (
+            BotCommand::WifiForget,
+            "!bbwififorget",
+            Permission::Broadcaster,
+        ),
+    ];
+
+    for (bot_command, command, permission) in defaults {
+        commands
+            .entry(bot_command)
+            .or_insert_with(|| CommandInformation {
+                command: command.to_string(),
+                permission,
+                whisper: false,
+                cooldown: 0,
+                cooldown_reply: false,
+                channels: HashMap::new(),
+                requires_online: None,
+            });
+    }
+}
 
-    commands
-        .entry(BotCommand::Stop)
-        .or_insert(CommandInformation {
-            command: "!bbstop".to_string(),
-            permission: Permission::Broadcaster,
-        });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    commands
-        .entry(BotCommand::Stats)
-        .or_insert(CommandInformation {
-            command: "!bbs".to_string(),
-            permission: Permission::Public,
-        });
+    fn commands() -> HashMap<BotCommand, CommandInformation> {
+        let mut commands = HashMap::new();
+        default_chat_commands(&mut commands);
+        commands
+    }
 
-    commands
-        .entry(BotCommand::Restart)
-        .or_insert(CommandInformation {
-            command: "!bbrs".to_string(),
-            permission: Permission::Broadcaster,
-        });
+    #[test]
+    fn default_commands_are_valid() {
+        assert!(validate_commands(&commands()).is_ok());
+    }
 
-    commands
-        .entry(BotCommand::Poweroff)
-        .or_insert(CommandInformation {
-            command: "!bbpo".to_string(),
-            permission: Permission::Broadcaster,
-        });
+    #[test]
+    fn preset_apply_changes() {
+        let mut config: crate::belabox::messages::Config = serde_json::from_str(r#"{"remote_key":"remote_key","max_br":5000,"delay":0,"pipeline":"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c","srt_latency":2000,"bitrate_overlay":false,"ssh_pass":null,"asrc":"No audio","acodec":"opus","relay_server":"1","relay_account":"1"}"#)
+            .unwrap();
+        let base = Preset::from(&config);
+        let changed = Preset {
+            srt_latency: 500,
+            ..base.clone()
+        };
 
-    commands
-        .entry(BotCommand::Bitrate)
-        .or_insert(CommandInformation {
-            command: "!bbb".to_string(),
-            permission: Permission::Broadcaster,
-        });
+        config.max_br = 3000;
+        changed.apply_changes(&base, &mut config);
 
-    commands
-        .entry(BotCommand::Sensor)
-        .or_insert(CommandInformation {
-            command: "!bbsensor".to_string(),
-            permission: Permission::Public,
-        });
+        assert_eq!(config.srt_latency, 500);
+        assert_eq!(config.max_br, 3000);
+    }
 
-    commands
-        .entry(BotCommand::Network)
-        .or_insert(CommandInformation {
-            command: "!bbt".to_string(),
-            permission: Permission::Broadcaster,
-        });
+    #[test]
+    fn empty_command() {
+        let mut commands = commands();
+        commands.get_mut(&BotCommand::Stats).unwrap().command = String::new();
 
-    commands
-        .entry(BotCommand::Latency)
-        .or_insert(CommandInformation {
-            command: "!bbl".to_string(),
-            permission: Permission::Broadcaster,
-        });
+        let error = validate_commands(&commands).unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::InvalidCommand(BotCommand::Stats)
+        ));
+    }
 
-    commands
-        .entry(BotCommand::AudioDelay)
-        .or_insert(CommandInformation {
-            command: "!bbd".to_string(),
-            permission: Permission::Broadcaster,
-        });
+    #[test]
+    fn duplicate_command() {
+        let mut commands = commands();
+        commands.get_mut(&BotCommand::Sensor).unwrap().command = "!bbs".to_string();
 
-    commands
-        .entry(BotCommand::Pipeline)
-        .or_insert(CommandInformation {
-            command: "!bbp".to_string(),
-            permission: Permission::Broadcaster,
-        });
+        let error = validate_commands(&commands).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The command !bbs is used by multiple commands: [Sensor, Stats]"
+        );
+    }
 
-    commands
-        .entry(BotCommand::AudioSrc)
-        .or_insert(CommandInformation {
-            command: "!bba".to_string(),
-            permission: Permission::Broadcaster,
-        });
+    #[test]
+    fn duplicate_command_ignores_case() {
+        let mut commands = commands();
+        commands.get_mut(&BotCommand::Sensor).unwrap().command = "!BBS".to_string();
+
+        let error = validate_commands(&commands).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The command !bbs is used by multiple commands: [Sensor, Stats]"
+        );
+    }
+
+    #[test]
+    fn command_with_spaces() {
+        let mut commands = commands();
+        commands.get_mut(&BotCommand::Start).unwrap().command = "!bb start".to_string();
+
+        let error = validate_commands(&commands).unwrap_err();
+        assert!(matches!(
+            error,
+            ConfigError::InvalidCommand(BotCommand::Start)
+        ));
+    }
+
+    #[test]
+    fn requires_online() {
+        let commands = commands();
+        let info = |command| &commands[&command];
+
+        assert!(info(BotCommand::Start).requires_online(&BotCommand::Start));
+        assert!(!info(BotCommand::Ping).requires_online(&BotCommand::Ping));
+
+        let info: CommandInformation = serde_json::from_str(
+            r#"{"command":"!bbs","permission":"Public","requires_online":false}"#,
+        )
+        .unwrap();
+        assert!(!info.requires_online(&BotCommand::Stats));
+    }
+
+    #[test]
+    fn channel_permission() {
+        let info: CommandInformation = serde_json::from_str(
+            r#"{"command":"!bbpo","permission":"Broadcaster","channels":{"Mods":"Moderator","other":null}}"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            info.permission_in("main"),
+            Some(Permission::Broadcaster)
+        ));
+        assert!(matches!(
+            info.permission_in("mods"),
+            Some(Permission::Moderator)
+        ));
+        assert!(info.permission_in("other").is_none());
+    }
 }
@@ -0,0 +1,101 @@
+use serde::Serialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DiscordError {
+    #[error("webhook request error")]
+    Request(#[from] reqwest::Error),
+}
+
+/// Posts messages to a Discord channel using a webhook.
+#[derive(Debug, Clone)]
+pub struct Discord {
+    client: reqwest::Client,
+    webhook: String,
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookMessage<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    embeds: Vec<Embed>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Embed {
+    pub title: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fields: Vec<EmbedField>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EmbedField {
+    pub name: String,
+    pub value: String,
+    pub inline: bool,
+}
+
+impl Discord {
+    pub fn new(webhook: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            webhook,
+        }
+    }
+
+    pub async fn send(&self, content: &str) -> Result<(), DiscordError> {
+        self.post(&WebhookMessage {
+            content: Some(content),
+            embeds: Vec::new(),
+        })
+        .await
+    }
+
+    pub async fn send_embed(&self, embed: Embed) -> Result<(), DiscordError> {
+        self.post(&WebhookMessage {
+            content: None,
+            embeds: vec![embed],
+        })
+        .await
+    }
+
+    async fn post(&self, message: &WebhookMessage<'_>) -> Result<(), DiscordError> {
+        self.client
+            .post(&self.webhook)
+            .json(message)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn embed() {
+        let message = WebhookMessage {
+            content: None,
+            embeds: vec![Embed {
+                title: "BELABOX".to_string(),
+                description: "eth0: 4000 kbps".to_string(),
+                fields: vec![EmbedField {
+                    name: "Temperature".to_string(),
+                    value: "52.1 °C".to_string(),
+                    inline: true,
+                }],
+            }],
+        };
+
+        let json = serde_json::to_string(&message).unwrap();
+        println!("{}", json);
+
+        let expected = r#"{"embeds":[{"title":"BELABOX","description":"eth0: 4000 kbps","fields":[{"name":"Temperature","value":"52.1 °C","inline":true}]}]}"#;
+        assert_eq!(expected, json);
+    }
+}
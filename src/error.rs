@@ -1,4 +1,4 @@
-use crate::{belabox, config, twitch};
+use crate::{belabox, config, discord, twitch};
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -13,4 +13,10 @@ pub enum Error {
     TwitchValide(#[from] twitch_irc::validate::Error),
     #[error("Twitch error")]
     Twitch(#[from] twitch::TwitchError),
+    #[error("HTTP server error")]
+    Http(#[source] std::io::Error),
+    #[error("Discord error")]
+    Discord(#[from] discord::DiscordError),
+    #[error("File error")]
+    File(#[source] std::io::Error),
 }
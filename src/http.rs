@@ -0,0 +1,452 @@
+//! A small HTTP server to read the BELABOX state from, e.g. for overlays.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    fmt::Write,
+    sync::Arc,
+};
+
+use axum::{
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    routing::{get, post},
+    Json, Router,
+};
+use tokio::{
+    net::TcpListener,
+    sync::{Mutex, RwLock},
+    task::AbortHandle,
+};
+
+use serde::Deserialize;
+use subtle::ConstantTimeEq;
+use tracing::info;
+
+use crate::{
+    belabox::messages::Netif,
+    bot::BelaState,
+    command_handler::{CommandHandler, Severity},
+    config::BotCommand,
+};
+
+#[derive(Clone)]
+struct AppState {
+    bela_state: Arc<RwLock<BelaState>>,
+    /// The tasks which have to be running for the bot to be healthy
+    tasks: Arc<Vec<AbortHandle>>,
+    /// Runs the commands, set once Twitch is connected
+    command_handler: Arc<Mutex<Option<CommandHandler>>>,
+    /// Running commands is disabled when empty
+    token: Arc<str>,
+}
+
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/total_bitrate", get(total_bitrate))
+        .route("/bitrate_history", get(bitrate_history))
+        .route("/rtt", get(rtt))
+        .route("/interfaces", get(interfaces))
+        .route("/metrics", get(metrics))
+        .route("/healthz", get(healthz))
+        .route("/start", post(start))
+        .route("/stop", post(stop))
+        .route("/command", post(command))
+        .with_state(state)
+}
+
+/// Serves the endpoints until the listener fails.
+pub async fn serve(
+    listener: TcpListener,
+    bela_state: Arc<RwLock<BelaState>>,
+    tasks: Arc<Vec<AbortHandle>>,
+    command_handler: Arc<Mutex<Option<CommandHandler>>>,
+    token: String,
+) -> Result<(), std::io::Error> {
+    let state = AppState {
+        bela_state,
+        tasks,
+        command_handler,
+        token: token.into(),
+    };
+
+    axum::serve(listener, router(state)).await
+}
+
+/// The total bitrate of all enabled interfaces in kbps.
+async fn total_bitrate(State(state): State<AppState>) -> String {
+    state.bela_state.read().await.total_bitrate().to_string()
+}
+
+/// The last bitrates in kbps of every interface, oldest first.
+async fn bitrate_history(State(state): State<AppState>) -> Json<HashMap<String, VecDeque<u64>>> {
+    Json(state.bela_state.read().await.bitrate_history.to_owned())
+}
+
+/// The round trip time to BELABOX Cloud in ms, no content until measured.
+async fn rtt(State(state): State<AppState>) -> Result<String, StatusCode> {
+    let rtt = state.bela_state.read().await.cloud_rtt;
+    let rtt = rtt.ok_or(StatusCode::NO_CONTENT)?;
+
+    Ok(rtt.as_millis().to_string())
+}
+
+/// Every interface with its bitrate, enabled flag and error, empty until the
+/// BELABOX sent them.
+async fn interfaces(State(state): State<AppState>) -> Json<HashMap<String, Netif>> {
+    let netifs = state.bela_state.read().await.netif.to_owned();
+    Json(netifs.unwrap_or_default())
+}
+
+/// The interface state in the Prometheus text format.
+async fn metrics(
+    State(state): State<AppState>,
+) -> ([(header::HeaderName, &'static str); 1], String) {
+    let netifs = state.bela_state.read().await.netif.to_owned();
+    let netifs = netifs
+        .unwrap_or_default()
+        .into_iter()
+        .collect::<BTreeMap<_, _>>();
+
+    let mut body = String::new();
+    body.push_str("# HELP belabot_interface_enabled Whether the interface is enabled.\n");
+    body.push_str("# TYPE belabot_interface_enabled gauge\n");
+    for (name, netif) in &netifs {
+        let _ = writeln!(
+            body,
+            "belabot_interface_enabled{{iface=\"{}\"}} {}",
+            escape_label(name),
+            u8::from(netif.enabled)
+        );
+    }
+
+    body.push_str("# HELP belabot_interface_error Whether the interface reports an error.\n");
+    body.push_str("# TYPE belabot_interface_error gauge\n");
+    for (name, netif) in &netifs {
+        let _ = writeln!(
+            body,
+            "belabot_interface_error{{iface=\"{}\",error=\"{}\"}} {}",
+            escape_label(name),
+            escape_label(netif.error.as_deref().unwrap_or_default()),
+            u8::from(netif.error.is_some())
+        );
+    }
+
+    let content_type = "text/plain; version=0.0.4";
+    ([(header::CONTENT_TYPE, content_type)], body)
+}
+
+/// Escapes a Prometheus label value.
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// OK while the tasks are running and BELABOX Cloud is still sending
+/// messages, unavailable otherwise.
+async fn healthz(State(state): State<AppState>) -> StatusCode {
+    let tasks_running = !state.tasks.iter().any(AbortHandle::is_finished);
+    let cloud_stale = state.bela_state.read().await.is_cloud_stale();
+
+    if tasks_running && !cloud_stale {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+}
+
+/// Starts the stream, replies the same as the chat command.
+async fn start(State(state): State<AppState>, headers: HeaderMap) -> (StatusCode, String) {
+    execute(&state, &headers, BotCommand::Start, "").await
+}
+
+/// Stops the stream, replies the same as the chat command.
+async fn stop(State(state): State<AppState>, headers: HeaderMap) -> (StatusCode, String) {
+    execute(&state, &headers, BotCommand::Stop, "").await
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandRequest {
+    command: BotCommand,
+    #[serde(default)]
+    args: String,
+}
+
+/// Runs any command with broadcaster permissions, replies the same as the
+/// chat command.
+async fn command(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(request): Json<CommandRequest>,
+) -> (StatusCode, String) {
+    execute(&state, &headers, request.command, &request.args).await
+}
+
+/// Runs the command after checking the bearer token.
+async fn execute(
+    state: &AppState,
+    headers: &HeaderMap,
+    command: BotCommand,
+    args: &str,
+) -> (StatusCode, String) {
+    if state.token.is_empty() {
+        return (StatusCode::FORBIDDEN, "No token configured".to_string());
+    }
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+
+    // Constant time so the response time doesn't tell how much matched
+    let valid = token.is_some_and(|t| bool::from(t.as_bytes().ct_eq(state.token.as_bytes())));
+    if !valid {
+        return (StatusCode::UNAUTHORIZED, "Invalid token".to_string());
+    }
+
+    // Don't hold the lock while waiting for the BELABOX
+    let handler = state.command_handler.lock().await.clone();
+    let Some(handler) = handler else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Not ready yet".to_string());
+    };
+
+    let online = state.bela_state.read().await.online;
+    if !online && handler.requires_online(&command).await {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Offline".to_string());
+    }
+
+    info!(?command, "HTTP used command");
+
+    let outcome = handler.execute(command, args.split_whitespace()).await;
+    let status = match outcome.severity {
+        Severity::Info => StatusCode::OK,
+        Severity::Warning => StatusCode::BAD_REQUEST,
+        Severity::Error => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    (status, outcome.reply)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::belabox::messages::Netif;
+
+    fn netif(tp: u64, enabled: bool) -> Netif {
+        Netif {
+            ip: "192.168.1.10".to_string(),
+            txb: None,
+            tp,
+            enabled,
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn total_bitrate() {
+        let bela_state = Arc::new(RwLock::new(BelaState::default()));
+        bela_state.write().await.netif = Some(HashMap::from([
+            ("eth0".to_string(), netif(512000, true)),
+            ("usb0".to_string(), netif(128000, true)),
+            ("usb1".to_string(), netif(128000, false)),
+        ]));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/total_bitrate", listener.local_addr().unwrap());
+        tokio::spawn(serve(
+            listener,
+            bela_state,
+            Default::default(),
+            Default::default(),
+            String::new(),
+        ));
+
+        let body = reqwest::get(url).await.unwrap().text().await.unwrap();
+        assert_eq!(body, "5000");
+    }
+
+    #[tokio::test]
+    async fn metrics() {
+        let bela_state = Arc::new(RwLock::new(BelaState::default()));
+        let mut usb0 = netif(128000, false);
+        usb0.error = Some("no \"carrier\"".to_string());
+        bela_state.write().await.netif = Some(HashMap::from([
+            ("eth0".to_string(), netif(512000, true)),
+            ("usb0".to_string(), usb0),
+        ]));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+        tokio::spawn(serve(
+            listener,
+            bela_state,
+            Default::default(),
+            Default::default(),
+            String::new(),
+        ));
+
+        let body = reqwest::get(url).await.unwrap().text().await.unwrap();
+        assert!(body.contains("belabot_interface_enabled{iface=\"eth0\"} 1\n"));
+        assert!(body.contains("belabot_interface_enabled{iface=\"usb0\"} 0\n"));
+        assert!(body.contains("belabot_interface_error{iface=\"eth0\",error=\"\"} 0\n"));
+        assert!(body
+            .contains("belabot_interface_error{iface=\"usb0\",error=\"no \\\"carrier\\\"\"} 1\n"));
+    }
+
+    #[tokio::test]
+    async fn healthz() {
+        let bela_state = Arc::new(RwLock::new(BelaState::default()));
+        let running = tokio::spawn(std::future::pending::<()>());
+        let finished = tokio::spawn(async {});
+        let finished_handle = finished.abort_handle();
+        finished.await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/healthz", listener.local_addr().unwrap());
+        let tasks = Arc::new(vec![running.abort_handle()]);
+        tokio::spawn(serve(
+            listener,
+            bela_state.clone(),
+            tasks,
+            Default::default(),
+            String::new(),
+        ));
+
+        let status = |url| async move { reqwest::get(url).await.unwrap().status() };
+
+        // Nothing received from BELABOX Cloud yet
+        assert_eq!(status(url.clone()).await, StatusCode::SERVICE_UNAVAILABLE);
+
+        bela_state.write().await.last_message = Some(tokio::time::Instant::now());
+        assert_eq!(status(url.clone()).await, StatusCode::OK);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/healthz", listener.local_addr().unwrap());
+        let tasks = Arc::new(vec![running.abort_handle(), finished_handle]);
+        tokio::spawn(serve(
+            listener,
+            bela_state,
+            tasks,
+            Default::default(),
+            String::new(),
+        ));
+
+        assert_eq!(status(url).await, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn start_requires_token() {
+        let bela_state = Arc::new(RwLock::new(BelaState::default()));
+        let client = reqwest::Client::new();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/start", listener.local_addr().unwrap());
+        tokio::spawn(serve(
+            listener,
+            bela_state.clone(),
+            Default::default(),
+            Default::default(),
+            String::new(),
+        ));
+
+        let response = client.post(&url).bearer_auth("").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/start", listener.local_addr().unwrap());
+        tokio::spawn(serve(
+            listener,
+            bela_state,
+            Default::default(),
+            Default::default(),
+            "secret".to_string(),
+        ));
+
+        let response = client.post(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client.post(&url).bearer_auth("wrong").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // No command handler yet
+        let response = client
+            .post(&url)
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let url = url.replace("/start", "/command");
+        let response = client
+            .post(&url)
+            .json(&serde_json::json!({"command": "Bitrate", "args": "4500"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = client
+            .post(&url)
+            .bearer_auth("secret")
+            .json(&serde_json::json!({"command": "Unknown"}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    /// Waits until the BELABOX reports the given streaming state.
+    async fn wait_for_streaming(bela_state: &RwLock<BelaState>, is_streaming: bool) {
+        tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+            while bela_state.read().await.is_streaming != is_streaming {
+                tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("timed out waiting for the streaming state");
+    }
+
+    #[tokio::test]
+    async fn stop_twice() {
+        let mut server = crate::command_handler::tests::server().await;
+        let handler = crate::command_handler::tests::handler(&mut server).await;
+        let bela_state = handler.bela_state.clone();
+
+        handler.start().await.unwrap();
+        wait_for_streaming(&bela_state, true).await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stop", listener.local_addr().unwrap());
+        tokio::spawn(serve(
+            listener,
+            bela_state.clone(),
+            Default::default(),
+            Arc::new(Mutex::new(Some(handler))),
+            "secret".to_string(),
+        ));
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(&url)
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "Stopping BELABOX");
+
+        wait_for_streaming(&bela_state, false).await;
+
+        // Already stopped isn't a failed request
+        let response = client
+            .post(&url)
+            .bearer_auth("secret")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "Already stopped");
+    }
+}
@@ -2,13 +2,19 @@ pub mod belabox;
 pub mod bot;
 mod command_handler;
 pub mod config;
+pub mod discord;
 pub mod error;
+pub mod http;
 mod monitor;
+pub mod sink;
 pub mod twitch;
 
 pub use belabox::Belabox;
 pub use bot::Bot;
 use command_handler::CommandHandler;
+pub use command_handler::CommandMessage;
 pub use config::Settings;
+pub use discord::Discord;
 use monitor::Monitor;
+pub use sink::MessageSink;
 pub use twitch::Twitch;
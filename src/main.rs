@@ -11,13 +11,14 @@ async fn main() -> Result<()> {
         env::set_var("RUST_LOG", "belabot=info");
     }
 
-    if cfg!(windows) {
-        tracing_subscriber::fmt()
-            .with_env_filter(EnvFilter::from_default_env())
-            .with_ansi(false)
-            .init();
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+
+    if log_format().is_some_and(|f| f.eq_ignore_ascii_case("json")) {
+        subscriber.json().init();
+    } else if cfg!(windows) {
+        subscriber.with_ansi(false).init();
     } else {
-        tracing_subscriber::fmt::init();
+        subscriber.init();
     }
 
     let config = match Settings::load("config.json") {
@@ -25,13 +26,19 @@ async fn main() -> Result<()> {
         Err(_) => Settings::ask_for_settings().await?,
     };
 
-    let bot = Bot::new(config).await?;
+    let mut bot = Bot::new(config).await?;
 
     // There is no way to recover when any of these stop, so stop the program
-    tokio::select! {
-        _ = bot.bb_msg_handle => {}
-        _ = bot.tw_msg_handle => {}
-    };
+    bot.run().await;
 
     Ok(())
 }
+
+/// Gets the log format from the `--log-format` flag or the
+/// `BELABOT_LOG_FORMAT` environment variable.
+fn log_format() -> Option<String> {
+    env::args()
+        .skip_while(|arg| arg != "--log-format")
+        .nth(1)
+        .or_else(|| env::var("BELABOT_LOG_FORMAT").ok())
+}
@@ -1,51 +1,185 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::Display,
+    sync::Arc,
+    time::Duration,
+};
 
 use tokio::{
     sync::{broadcast, Mutex, RwLock},
-    time::Instant,
+    time::{self, Instant},
 };
 use tracing::{error, warn};
 
 use crate::{
     belabox::{self, messages, Message},
-    bot::BelaState,
-    command_handler, config, Belabox, Twitch,
+    bot::{self, BelaState},
+    command_handler::{self, interface_name, sorted_interfaces, Severity},
+    config::{self, Alert, AlertRoute},
+    Discord, MessageSink,
 };
 
+/// How long after a start request a notification counts as a failed start.
+const START_FAILED_WINDOW: Duration = Duration::from_secs(5);
+
+/// The default messages of the alerts by their key, the keys in the config
+/// replace them.
+pub const DEFAULT_MESSAGES: &[(&str, &str)] = &[
+    ("modems", "BB: {changes}"),
+    ("connected", "{interfaces} {verb} now connected"),
+    ("disconnected", "{interfaces} {verb} disconnected"),
+    ("flapping", "{interfaces} {verb} flapping"),
+    ("ups_charging", "BB: UPS charging"),
+    ("ups_not_charging", "BB: UPS not charging"),
+    (
+        "temperature_high",
+        "BB: Temperature is high ({temperature})",
+    ),
+    (
+        "temperature_normal",
+        "BB: Temperature is back to normal ({temperature})",
+    ),
+    (
+        "stream_stopped",
+        "BB: Stream stopped unexpectedly, restarting in {delay} seconds",
+    ),
+    (
+        "stream_keeps_stopping",
+        "BB: Stream keeps stopping, not restarting it automatically for now",
+    ),
+    ("start_failed", "BB: Start failed: {reason}"),
+    (
+        "target_low",
+        "BB: Network can't sustain target (measured {measured} / target {target} kbps)",
+    ),
+    (
+        "target_recovered",
+        "BB: Network sustains the target again (measured {measured} / target {target} kbps)",
+    ),
+    (
+        "updates",
+        "BB: {count} updates available ({size}), update between streams",
+    ),
+    ("notification", "BB: {message}"),
+    ("config_changed", "BB: Config changed externally: {changes}"),
+];
+
+/// More automatic actions than this within the window means the bot is
+/// reacting to its own changes.
+const LOOP_MAX_ACTIONS: usize = 3;
+const LOOP_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// How long automatic actions are suppressed once a loop is detected.
+const LOOP_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+
+/// How far above the target ratio the measured bitrate has to get before
+/// it's announced as recovered, so it doesn't flap around the ratio.
+const TARGET_RECOVERY_MARGIN: f64 = 0.1;
+
 pub struct Monitor {
-    pub belabox: Arc<Belabox>,
     pub bela_state: Arc<RwLock<BelaState>>,
-    pub twitch: Arc<Twitch>,
+    pub sink: Arc<dyn MessageSink>,
+    pub discord: Option<Discord>,
+    pub alerts: HashMap<Alert, AlertRoute>,
     pub command_handler: Arc<Mutex<Option<command_handler::CommandHandler>>>,
     pub custom_interface_name: HashMap<String, String>,
+    /// Replacements for the default messages by their key
+    pub messages: HashMap<String, String>,
 }
 
 impl Monitor {
     pub async fn run(
         &self,
-        mut messages: broadcast::Receiver<belabox::Message>,
+        mut messages: broadcast::Receiver<belabox::Event>,
         monitor: config::Monitor,
     ) {
-        while let Ok(message) = messages.recv().await {
+        let modems_window = Duration::from_secs(monitor.modems_window);
+        let mut modem_changes = ModemChanges::default();
+        let mut was_streaming = false;
+        let mut previous_config = None;
+        let dropped = self.bela_state.read().await.dropped_messages.clone();
+
+        for key in self.messages.keys() {
+            if !DEFAULT_MESSAGES.iter().any(|(k, _)| k == key) {
+                warn!(key, "unknown monitor message");
+            }
+        }
+
+        loop {
+            let deadline = modem_changes.deadline;
+            let event = tokio::select! {
+                event = bot::recv(&mut messages, &dropped) => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = time::sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    self.announce_modems(std::mem::take(&mut modem_changes)).await;
+                    continue;
+                }
+            };
+
+            let belabox::Event::Message(message) = event else {
+                continue;
+            };
+
             match message {
+                Message::Config(config) => {
+                    if monitor.config {
+                        self.config_changed(previous_config.as_ref(), &config).await;
+                    }
+
+                    previous_config = Some(config);
+                }
                 Message::Netif(netif) => {
                     if monitor.modems {
-                        self.modems(netif).await;
+                        self.modems(netif, &mut modem_changes, modems_window).await;
                     }
 
-                    if monitor.network {
-                        self.network(monitor.network_timeout).await;
+                    if monitor.target {
+                        let duration = Duration::from_secs(monitor.target_duration);
+                        self.target(monitor.target_ratio, duration).await;
                     }
+
+                    self.network().await;
                 }
                 Message::Sensors(sensors) => {
                     if monitor.ups {
-                        self.ups(sensors, monitor.ups_plugged_in).await;
+                        self.ups(&sensors, monitor.ups_plugged_in).await;
+                    }
+
+                    if monitor.temperature {
+                        self.temperature(&sensors, monitor.temperature_threshold)
+                            .await;
+                    }
+                }
+                Message::Status(status) => {
+                    let updates = match &status {
+                        messages::StatusKind::Status(s) => Some(&s.available_updates),
+                        messages::StatusKind::AvailableUpdates(u) => Some(&u.available_updates),
+                        _ => None,
+                    };
+
+                    if let (true, Some(updates)) = (monitor.updates, updates) {
+                        self.updates(updates.as_ref()).await;
+                    }
+
+                    let is_streaming = match status {
+                        messages::StatusKind::Status(s) => s.is_streaming,
+                        messages::StatusKind::StreamingStatus(s) => s.is_streaming,
+                        _ => continue,
+                    };
+
+                    let stopped = was_streaming && !is_streaming;
+                    was_streaming = is_streaming;
+
+                    if stopped && monitor.auto_restart {
+                        self.stream_stopped(monitor.auto_restart_delay).await;
                     }
                 }
                 Message::Notification(messages::Notifications::Show(notification)) => {
-                    if monitor.notifications {
-                        self.notifications(notification, monitor.notification_timeout)
-                            .await;
+                    let start_failed = self.start_failed(&notification).await;
+
+                    if monitor.notifications && !start_failed {
+                        self.notifications(notification).await;
                     }
                 }
                 _ => {}
@@ -53,71 +187,173 @@ impl Monitor {
         }
     }
 
-    async fn send(&self, message: String) {
-        if let Err(e) = self.twitch.send(message).await {
-            error!(?e, "error sending message to twitch");
-        }
+    /// The configured or default message for the key with the `{name}`
+    /// placeholders replaced.
+    fn message(&self, key: &str, values: &[(&str, &dyn Display)]) -> String {
+        let template = self
+            .messages
+            .get(key)
+            .map(String::as_str)
+            .unwrap_or_else(|| {
+                DEFAULT_MESSAGES
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map_or("", |(_, m)| m)
+            });
+
+        values
+            .iter()
+            .fold(template.to_owned(), |message, (name, value)| {
+                message.replace(&format!("{{{}}}", name), &value.to_string())
+            })
     }
 
-    pub async fn modems(&self, netif: HashMap<String, messages::Netif>) {
-        let read = self.bela_state.read().await;
-        let previous = match &read.netif {
-            Some(p) => p,
-            None => return,
+    async fn send(&self, alert: Alert, message: String) {
+        let route = self.alerts.get(&alert).copied().unwrap_or_default();
+
+        let discord = match (&self.discord, route) {
+            (Some(discord), AlertRoute::Discord | AlertRoute::Both) => Some(discord),
+            _ => None,
         };
 
-        let netif_name = |n: &String| -> String {
-            if let Some(custom) = self.custom_interface_name.get(n) {
-                return custom.to_owned();
+        if let Some(discord) = discord {
+            if let Err(e) = discord.send(&message).await {
+                error!(?e, "error sending message to discord");
             }
+        }
+
+        if self.bela_state.write().await.is_muted() {
+            return;
+        }
 
-            let i = netif.get(n).unwrap();
-            if let Some(custom) = self.custom_interface_name.get(&i.ip) {
-                return custom.to_owned();
+        // Fall back to chat when there is no discord webhook configured
+        if route != AlertRoute::Discord || discord.is_none() {
+            if let Err(e) = self.sink.send(message).await {
+                error!(?e, "error sending message");
             }
+        }
+    }
 
-            n.to_owned()
+    /// Collects the interface changes, they are announced together once the
+    /// window has passed.
+    pub async fn modems(
+        &self,
+        netif: HashMap<String, messages::Netif>,
+        changes: &mut ModemChanges,
+        window: Duration,
+    ) {
+        let previous = match &self.bela_state.read().await.netif {
+            Some(p) => p.to_owned(),
+            None => return,
         };
 
-        let added = netif
+        let changed = netif
             .keys()
-            .filter(|&n| !previous.contains_key(n))
-            .map(netif_name)
+            .filter(|n| !previous.contains_key(*n))
+            .chain(previous.keys().filter(|n| !netif.contains_key(*n)))
+            .cloned()
             .collect::<Vec<String>>();
 
-        let removed = previous
+        if changed.is_empty() {
+            return;
+        }
+
+        if changes.deadline.is_none() {
+            changes.deadline = Some(Instant::now() + window);
+            changes.before = previous;
+        }
+
+        for name in changed {
+            *changes.count.entry(name).or_default() += 1;
+        }
+
+        changes.after = netif;
+
+        if window.is_zero() {
+            self.announce_modems(std::mem::take(changes)).await;
+        }
+    }
+
+    async fn announce_modems(&self, changes: ModemChanges) {
+        let ModemChanges {
+            before,
+            after,
+            count,
+            ..
+        } = changes;
+
+        let is_flapping = |n: &String| count.get(n).copied().unwrap_or_default() > 1;
+
+        let added = sorted_interfaces(&after)
+            .into_iter()
+            .filter(|(n, _)| !before.contains_key(*n) && !is_flapping(n))
+            .map(|(n, i)| interface_name(n, i, &self.custom_interface_name).to_owned())
+            .collect::<Vec<String>>();
+
+        let removed = sorted_interfaces(&before)
+            .into_iter()
+            .filter(|(n, _)| !after.contains_key(*n) && !is_flapping(n))
+            .map(|(n, i)| interface_name(n, i, &self.custom_interface_name).to_owned())
+            .collect::<Vec<String>>();
+
+        let mut flapping = count
             .keys()
-            .filter(|&n| !netif.contains_key(n))
-            .map(netif_name)
+            .filter(|n| is_flapping(n))
+            .filter_map(|n| after.get(n).or_else(|| before.get(n)).map(|i| (n, i)))
+            .map(|(n, i)| interface_name(n, i, &self.custom_interface_name).to_owned())
             .collect::<Vec<String>>();
+        flapping.sort();
 
+        if let Some(message) = self.modems_message(&added, &removed, &flapping) {
+            self.send(Alert::Modems, message).await;
+        }
+    }
+
+    fn modems_message(
+        &self,
+        added: &[String],
+        removed: &[String],
+        flapping: &[String],
+    ) -> Option<String> {
         let mut message = Vec::new();
 
         if !added.is_empty() {
-            let a = if added.len() > 1 { "are" } else { "is" };
+            let verb = if added.len() > 1 { "are" } else { "is" };
+            let interfaces = added.join(", ");
 
-            message.push(format!("{} {} now connected", added.join(", "), a));
+            message
+                .push(self.message("connected", &[("interfaces", &interfaces), ("verb", &verb)]));
         }
 
         if !removed.is_empty() {
-            let a = if removed.len() > 1 { "have" } else { "has" };
+            let verb = if removed.len() > 1 { "have" } else { "has" };
+            let interfaces = removed.join(", ");
 
-            message.push(format!("{} {} disconnected", removed.join(", "), a));
+            message.push(self.message(
+                "disconnected",
+                &[("interfaces", &interfaces), ("verb", &verb)],
+            ));
         }
 
-        if !message.is_empty() {
-            self.send(format!("BB: {}", message.join(", "))).await;
+        if !flapping.is_empty() {
+            let verb = if flapping.len() > 1 { "are" } else { "is" };
+            let interfaces = flapping.join(", ");
+
+            message.push(self.message("flapping", &[("interfaces", &interfaces), ("verb", &verb)]));
         }
+
+        if message.is_empty() {
+            return None;
+        }
+
+        Some(self.message("modems", &[("changes", &message.join(", "))]))
     }
 
-    pub async fn ups(&self, sensors: messages::Sensors, plugged_voltage: f64) {
-        let voltage = match &sensors.soc_voltage {
-            Some(v) => v,
-            None => return,
+    pub async fn ups(&self, sensors: &messages::Sensors, plugged_voltage: f64) {
+        let Some(voltage) = sensors.voltage() else {
+            return;
         };
 
-        let voltage = voltage.split_whitespace().next().unwrap();
-        let voltage = voltage.parse::<f64>().unwrap();
         let plugged_in = (voltage * 100.0).floor() / 100.0 >= plugged_voltage;
 
         let charging = {
@@ -141,19 +377,246 @@ impl Monitor {
         };
 
         if let Some(c) = charging {
-            let a = if !c { "not" } else { "" };
-            let msg = format!("BB: UPS {} charging", a);
+            let key = if c {
+                "ups_charging"
+            } else {
+                "ups_not_charging"
+            };
+            let msg = self.message(key, &[]);
 
-            self.send(msg).await;
+            self.send(Alert::Ups, msg).await;
         }
     }
 
-    pub async fn notifications(
-        &self,
-        notification: messages::NotificationShow,
-        notification_timeout: u64,
-    ) {
+    pub async fn temperature(&self, sensors: &messages::Sensors, threshold: f64) {
+        let Some(temperature) = sensors.temperature() else {
+            return;
+        };
+
+        let overheating = temperature >= threshold;
+
+        let changed = {
+            let mut lock = self.bela_state.write().await;
+            let previous = lock.notify_temperature.replace(overheating);
+
+            previous.unwrap_or_default() != overheating
+        };
+
+        if changed {
+            let key = if overheating {
+                "temperature_high"
+            } else {
+                "temperature_normal"
+            };
+            let msg = self.message(key, &[("temperature", &sensors.soc_temperature)]);
+
+            self.send(Alert::Temperature, msg).await;
+        }
+    }
+
+    /// Announces the stream settings changed outside of the bot, e.g. in
+    /// belaUI, compared to the config belaUI reported before.
+    async fn config_changed(&self, previous: Option<&messages::Config>, config: &messages::Config) {
+        let (expected, pipelines) = {
+            let mut lock = self.bela_state.write().await;
+            let expected = lock.expected_config.to_owned();
+
+            // belaUI applied the changes of the bot
+            if expected
+                .as_ref()
+                .is_some_and(|e| config::Preset::from(e) == config::Preset::from(config))
+            {
+                lock.expected_config = None;
+            }
+
+            (expected, lock.pipelines.to_owned())
+        };
+
+        let Some(previous) = previous else {
+            return;
+        };
+
+        let mut changes = config_changes(previous, config, pipelines.as_ref());
+
+        // Only what differs from the changes the bot made
+        if let Some(expected) = &expected {
+            let unexpected = config_changes(expected, config, pipelines.as_ref());
+            changes.retain(|c| unexpected.contains(c));
+        }
+
+        if changes.is_empty() {
+            return;
+        }
+
+        let msg = self.message("config_changed", &[("changes", &changes.join(", "))]);
+        self.send(Alert::Config, msg).await;
+    }
+
+    /// Starts the stream again after the delay when it stopped without
+    /// anyone asking for it.
+    pub async fn stream_stopped(&self, delay: u64) {
+        let allowed = {
+            let mut lock = self.bela_state.write().await;
+            if !lock.online || lock.stop_requested || lock.restart.is_some() {
+                return;
+            }
+
+            lock.auto_actions.allow()
+        };
+
+        warn!("stream stopped unexpectedly");
+
+        if !allowed {
+            let msg = self.message("stream_keeps_stopping", &[]);
+            self.send(Alert::Restart, msg).await;
+
+            return;
+        }
+
+        let msg = self.message("stream_stopped", &[("delay", &delay)]);
+        self.send(Alert::Restart, msg).await;
+
+        let bela_state = self.bela_state.clone();
+        let command_handler = self.command_handler.clone();
+
+        tokio::spawn(async move {
+            time::sleep(Duration::from_secs(delay)).await;
+
+            let lock = command_handler.lock().await;
+            let Some(ch) = &*lock else { return };
+            let _operation = ch.operation.lock().await;
+
+            {
+                let lock = bela_state.read().await;
+                if lock.is_streaming || !lock.online || lock.stop_requested {
+                    return;
+                }
+            }
+
+            match ch.start().await {
+                Ok(outcome) if outcome.severity == Severity::Error => {
+                    error!(reply = outcome.reply, "error restarting the stream")
+                }
+                Err(e) => error!(?e, "error restarting the stream"),
+                _ => {}
+            }
+        });
+    }
+
+    /// Reports a warning or error shortly after a start request as a failed
+    /// start, returns if it was reported.
+    pub async fn start_failed(&self, notification: &messages::NotificationShow) -> bool {
+        let reason = {
+            let mut lock = self.bela_state.write().await;
+
+            let Some(requested) = lock.start_requested else {
+                return false;
+            };
+
+            if requested.elapsed() > START_FAILED_WINDOW {
+                lock.start_requested = None;
+                return false;
+            }
+
+            let reason = notification
+                .show
+                .iter()
+                .find(|n| n.kind == "error" || n.kind == "warning")
+                .map(|n| n.msg.to_owned());
+
+            if reason.is_some() {
+                lock.start_requested = None;
+            }
+
+            reason
+        };
+
+        let Some(reason) = reason else {
+            return false;
+        };
+
+        let msg = self.message("start_failed", &[("reason", &reason)]);
+        self.send(Alert::Notifications, msg).await;
+
+        true
+    }
+
+    /// Announces when the measured bitrate stayed below the ratio of the max
+    /// bitrate for the duration while streaming, and when it recovers.
+    pub async fn target(&self, ratio: f64, duration: Duration) {
+        let msg = {
+            let mut lock = self.bela_state.write().await;
+
+            let target = match &lock.config {
+                Some(config) if lock.is_streaming => config.max_br as f64,
+                _ => {
+                    lock.below_target_since = None;
+                    lock.notify_target = false;
+                    return;
+                }
+            };
+
+            let measured = lock.total_bitrate();
+
+            if (measured as f64) < target * ratio {
+                let since = *lock.below_target_since.get_or_insert_with(Instant::now);
+
+                if lock.notify_target || since.elapsed() < duration {
+                    return;
+                }
+
+                lock.notify_target = true;
+                self.message(
+                    "target_low",
+                    &[("measured", &measured), ("target", &target)],
+                )
+            } else {
+                lock.below_target_since = None;
+
+                if !lock.notify_target
+                    || (measured as f64) < target * (ratio + TARGET_RECOVERY_MARGIN)
+                {
+                    return;
+                }
+
+                lock.notify_target = false;
+                self.message(
+                    "target_recovered",
+                    &[("measured", &measured), ("target", &target)],
+                )
+            }
+        };
+
+        self.send(Alert::Target, msg).await;
+    }
+
+    /// Announces the available updates once, until their number changes.
+    pub async fn updates(&self, updates: Option<&messages::AvailableUpdates>) {
+        let updates = updates.filter(|u| u.package_count > 0);
+        let count = updates.map_or(0, |u| u.package_count);
+
+        let changed = {
+            let mut lock = self.bela_state.write().await;
+            let previous = lock.available_updates.replace(count);
+
+            previous.unwrap_or_default() != count
+        };
+
+        if let (true, Some(updates)) = (changed, updates) {
+            let msg = self.message(
+                "updates",
+                &[
+                    ("count", &updates.package_count),
+                    ("size", &updates.download_size),
+                ],
+            );
+            self.send(Alert::Updates, msg).await;
+        }
+    }
+
+    pub async fn notifications(&self, notification: messages::NotificationShow) {
         let mut lock = self.bela_state.write().await;
+        let notification_timeout = lock.notification_window;
         let timeout = &mut lock.notification_timeout;
 
         let now = Instant::now();
@@ -171,19 +634,22 @@ impl Monitor {
                 .and_modify(|n| *n = now)
                 .or_insert(now);
 
-            self.send("BB: ".to_owned() + &notification.msg).await;
+            let msg = self.message("notification", &[("message", &notification.msg)]);
+            self.send(Alert::Notifications, msg).await;
         }
     }
 
-    pub async fn network(&self, network_timeout: u64) {
+    /// Posts the stats while streaming when the automatic stats are enabled.
+    pub async fn network(&self) {
         {
             let mut lock = self.bela_state.write().await;
-            if !lock.is_streaming {
+            if !lock.is_streaming || !lock.auto_stats {
                 return;
             }
 
+            let interval = Duration::from_secs(lock.auto_stats_interval);
             let timeout = &mut lock.network_timeout;
-            if timeout.elapsed() < Duration::from_secs(network_timeout) {
+            if timeout.elapsed() < interval {
                 return;
             } else {
                 *timeout = Instant::now();
@@ -192,8 +658,365 @@ impl Monitor {
 
         let lock = self.command_handler.lock().await;
         let Some(ch) = &*lock else { return };
-        let Ok(msg) = ch.stats().await else { return };
+        let stats = ch.monitor_stats().await;
+
+        self.send(Alert::Network, stats).await;
+    }
+}
+
+/// Describes the stream settings which differ between the configs.
+pub(crate) fn config_changes(
+    previous: &messages::Config,
+    config: &messages::Config,
+    pipelines: Option<&HashMap<String, messages::Pipeline>>,
+) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    if previous.max_br != config.max_br {
+        changes.push(format!("max bitrate {} kbps", config.max_br));
+    }
+
+    if previous.srt_latency != config.srt_latency {
+        changes.push(format!("SRT latency {} ms", config.srt_latency));
+    }
+
+    if previous.asrc != config.asrc {
+        changes.push(format!("audio source {}", config.asrc));
+    }
+
+    if previous.pipeline != config.pipeline {
+        let name = pipelines
+            .and_then(|p| p.get(&config.pipeline))
+            .map(|p| command_handler::split_pipeline(&p.name).1)
+            .unwrap_or(&config.pipeline);
+
+        changes.push(format!("pipeline {}", name));
+    }
+
+    changes
+}
+
+/// The recent automatic actions, used to stop the bot from acting in a loop
+/// on the results of its own actions.
+#[derive(Debug, Default)]
+pub struct LoopGuard {
+    actions: VecDeque<Instant>,
+    suppressed_until: Option<Instant>,
+}
+
+impl LoopGuard {
+    /// Records an automatic action, `false` when it's suppressed because
+    /// too many happened recently.
+    pub fn allow(&mut self) -> bool {
+        let now = Instant::now();
+
+        if self.suppressed_until.is_some_and(|s| s > now) {
+            return false;
+        }
+        self.suppressed_until = None;
+
+        while self
+            .actions
+            .front()
+            .is_some_and(|a| now.duration_since(*a) > LOOP_WINDOW)
+        {
+            self.actions.pop_front();
+        }
+
+        if self.actions.len() >= LOOP_MAX_ACTIONS {
+            warn!(
+                actions = self.actions.len(),
+                "automatic actions are oscillating, suppressing them for {:?}", LOOP_COOLDOWN
+            );
+            self.actions.clear();
+            self.suppressed_until = Some(now + LOOP_COOLDOWN);
+            return false;
+        }
+
+        self.actions.push_back(now);
+        true
+    }
+}
+
+/// Interface changes collected during the modems window, an interface which
+/// changes more than once in the window is announced as flapping.
+#[derive(Debug, Default)]
+pub struct ModemChanges {
+    deadline: Option<Instant>,
+    /// The interfaces before the first change
+    before: HashMap<String, messages::Netif>,
+    after: HashMap<String, messages::Netif>,
+    count: HashMap<String, u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    /// Keeps every message the monitor sends.
+    #[derive(Default)]
+    struct TestSink(std::sync::Mutex<Vec<String>>);
+
+    #[async_trait::async_trait]
+    impl MessageSink for TestSink {
+        async fn send(&self, message: String) -> Result<(), Error> {
+            self.0.lock().unwrap().push(message);
+            Ok(())
+        }
+    }
+
+    fn monitor(sink: Arc<TestSink>) -> Monitor {
+        Monitor {
+            bela_state: Default::default(),
+            sink,
+            discord: None,
+            alerts: HashMap::new(),
+            command_handler: Default::default(),
+            custom_interface_name: HashMap::new(),
+            messages: HashMap::new(),
+        }
+    }
+
+    fn notification(kind: &str) -> messages::NotificationShow {
+        messages::NotificationShow {
+            show: vec![messages::NotificationMessage {
+                duration: 2,
+                is_dismissable: false,
+                is_persistent: true,
+                msg: "Selected audio input 'HDMI' is unavailable".to_string(),
+                name: "asrc_not_found".to_string(),
+                kind: kind.to_string(),
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn start_failed() {
+        let sink = Arc::new(TestSink::default());
+        let monitor = monitor(sink.clone());
+        monitor.bela_state.write().await.start_requested = Some(Instant::now());
+
+        assert!(!monitor.start_failed(&notification("success")).await);
+        assert!(monitor.start_failed(&notification("warning")).await);
+        // Only reported once per start
+        assert!(!monitor.start_failed(&notification("warning")).await);
+
+        let messages = sink.0.lock().unwrap();
+        assert_eq!(
+            *messages,
+            ["BB: Start failed: Selected audio input 'HDMI' is unavailable"]
+        );
+    }
+
+    #[tokio::test]
+    async fn target() {
+        let sink = Arc::new(TestSink::default());
+        let monitor = monitor(sink.clone());
+        let set_bitrate = |kbps: u64| {
+            let state = monitor.bela_state.clone();
+            async move {
+                let mut lock = state.write().await;
+                lock.is_streaming = true;
+                lock.config = Some(messages::Config {
+                    max_br: 4500,
+                    ..Default::default()
+                });
+                lock.netif = Some(HashMap::from([(
+                    "usb0".to_string(),
+                    messages::Netif {
+                        ip: "10.0.0.2".to_string(),
+                        txb: None,
+                        tp: kbps * 1024 / 8,
+                        enabled: true,
+                        error: None,
+                    },
+                )]));
+            }
+        };
+
+        set_bitrate(2100).await;
+        monitor.target(0.6, Duration::ZERO).await;
+        monitor.target(0.6, Duration::ZERO).await;
+
+        // Above the ratio but within the recovery margin
+        set_bitrate(2900).await;
+        monitor.target(0.6, Duration::ZERO).await;
+
+        set_bitrate(4000).await;
+        monitor.target(0.6, Duration::ZERO).await;
+
+        set_bitrate(2100).await;
+        monitor.target(0.6, Duration::from_secs(60)).await;
+
+        let messages = sink.0.lock().unwrap();
+        assert_eq!(
+            *messages,
+            [
+                "BB: Network can't sustain target (measured 2100 / target 4500 kbps)",
+                "BB: Network sustains the target again (measured 4000 / target 4500 kbps)"
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_guard() {
+        let mut guard = LoopGuard::default();
+
+        for _ in 0..LOOP_MAX_ACTIONS {
+            assert!(guard.allow());
+        }
+
+        assert!(!guard.allow());
+        assert!(!guard.allow());
+    }
+
+    #[tokio::test]
+    async fn updates() {
+        let sink = Arc::new(TestSink::default());
+        let monitor = monitor(sink.clone());
+        let updates = |package_count| messages::AvailableUpdates {
+            package_count,
+            download_size: "52.3 MB".to_string(),
+        };
+
+        monitor.updates(Some(&updates(12))).await;
+        monitor.updates(Some(&updates(12))).await;
+        monitor.updates(None).await;
+        monitor.updates(Some(&updates(3))).await;
+
+        let messages = sink.0.lock().unwrap();
+        assert_eq!(
+            *messages,
+            [
+                "BB: 12 updates available (52.3 MB), update between streams",
+                "BB: 3 updates available (52.3 MB), update between streams"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn muted() {
+        let sink = Arc::new(TestSink::default());
+        let monitor = monitor(sink.clone());
+
+        monitor.bela_state.write().await.muted_until =
+            Some(Instant::now() + Duration::from_secs(60));
+        monitor.send(Alert::Modems, "muted".to_string()).await;
+
+        // The auto restart announcements are muted as well
+        monitor.bela_state.write().await.online = true;
+        monitor.stream_stopped(0).await;
+
+        monitor.bela_state.write().await.muted_until = Some(Instant::now());
+        monitor.send(Alert::Modems, "unmuted".to_string()).await;
+
+        assert_eq!(*sink.0.lock().unwrap(), ["unmuted"]);
+        assert_eq!(monitor.bela_state.read().await.muted_until, None);
+    }
+
+    #[test]
+    fn config_changes() {
+        let previous: messages::Config =
+            serde_json::from_str(r#"{"remote_key":"remote_key","max_br":5000,"delay":0,"pipeline":"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c","srt_latency":2000,"bitrate_overlay":false,"ssh_pass":null,"asrc":"No audio","acodec":"opus","relay_server":"1","relay_account":"1"}"#)
+                .unwrap();
+
+        let config = messages::Config {
+            max_br: 4000,
+            asrc: "USB audio".to_string(),
+            ..previous.clone()
+        };
+
+        let changes = super::config_changes(&previous, &config, None);
+        assert_eq!(changes, ["max bitrate 4000 kbps", "audio source USB audio"]);
+        assert!(super::config_changes(&config, &config, None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn config_changed() {
+        let sink = Arc::new(TestSink::default());
+        let monitor = monitor(sink.clone());
+
+        let previous: messages::Config = serde_json::from_str(r#"{"remote_key":"remote_key","max_br":5000,"delay":0,"pipeline":"7ca3d9dd20726a7c2dad06948e1eadc6f84c461c","srt_latency":2000,"bitrate_overlay":false,"ssh_pass":null,"asrc":"No audio","acodec":"opus","relay_server":"1","relay_account":"1"}"#)
+            .unwrap();
+
+        // The first config isn't a change
+        monitor.config_changed(None, &previous).await;
+
+        // The bot changed the bitrate, someone else the audio source
+        let expected = messages::Config {
+            max_br: 4000,
+            ..previous.clone()
+        };
+        monitor.bela_state.write().await.expected_config = Some(expected.clone());
+        let config = messages::Config {
+            asrc: "USB audio".to_string(),
+            ..expected.clone()
+        };
+        monitor.config_changed(Some(&previous), &config).await;
+
+        // Once belaUI applied the changes of the bot all changes count
+        monitor.bela_state.write().await.expected_config = Some(config.clone());
+        monitor.config_changed(Some(&config), &config).await;
+        assert!(monitor.bela_state.read().await.expected_config.is_none());
+
+        let external = messages::Config {
+            max_br: 3000,
+            ..config.clone()
+        };
+        monitor.config_changed(Some(&config), &external).await;
+
+        assert_eq!(
+            *sink.0.lock().unwrap(),
+            [
+                "BB: Config changed externally: audio source USB audio",
+                "BB: Config changed externally: max bitrate 3000 kbps"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_start_requested() {
+        let sink = Arc::new(TestSink::default());
+        let monitor = monitor(sink.clone());
+
+        assert!(!monitor.start_failed(&notification("error")).await);
+        assert!(sink.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn modems_message_flapping() {
+        let added = vec!["usb0".to_string()];
+        let flapping = vec!["eth0".to_string()];
+
+        let monitor = monitor(Default::default());
+        let message = monitor.modems_message(&added, &[], &flapping).unwrap();
+        assert_eq!(message, "BB: usb0 is now connected, eth0 is flapping");
+    }
+
+    #[test]
+    fn modems_message_empty() {
+        let monitor = monitor(Default::default());
+        assert_eq!(monitor.modems_message(&[], &[], &[]), None);
+    }
 
-        self.send(msg).await;
+    #[test]
+    fn custom_messages() {
+        let mut monitor = monitor(Default::default());
+        monitor.messages = HashMap::from([
+            ("modems".to_string(), "BELABOX: {changes}".to_string()),
+            (
+                "connected".to_string(),
+                "{interfaces} verbunden".to_string(),
+            ),
+        ]);
+
+        let added = vec!["usb0".to_string()];
+        let removed = vec!["eth0".to_string(), "wlan0".to_string()];
+        let message = monitor.modems_message(&added, &removed, &[]).unwrap();
+        assert_eq!(
+            message,
+            "BELABOX: usb0 verbunden, eth0, wlan0 have disconnected"
+        );
     }
 }
@@ -0,0 +1,117 @@
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+
+use crate::{error::Error, Discord, Twitch};
+
+/// Where the bot sends its chat messages to.
+///
+/// Replies and whispers fall back to a regular message for sinks which don't
+/// support them.
+#[async_trait]
+pub trait MessageSink: Send + Sync {
+    async fn send(&self, message: String) -> Result<(), Error>;
+
+    async fn reply(&self, _message_id: String, message: String) -> Result<(), Error> {
+        self.send(message).await
+    }
+
+    async fn whisper(&self, _user_id: &str, message: String) -> Result<(), Error> {
+        self.send(message).await
+    }
+}
+
+#[async_trait]
+impl MessageSink for Twitch {
+    async fn send(&self, message: String) -> Result<(), Error> {
+        Ok(Twitch::send(self, message).await?)
+    }
+
+    async fn reply(&self, message_id: String, message: String) -> Result<(), Error> {
+        Ok(Twitch::reply(self, message_id, message).await?)
+    }
+
+    async fn whisper(&self, user_id: &str, message: String) -> Result<(), Error> {
+        Ok(Twitch::whisper(self, user_id, message).await?)
+    }
+}
+
+/// Sends every message in a different Twitch channel than the one commands
+/// are read from.
+pub struct ChannelSink {
+    pub twitch: Arc<Twitch>,
+    pub channel: String,
+}
+
+#[async_trait]
+impl MessageSink for ChannelSink {
+    async fn send(&self, message: String) -> Result<(), Error> {
+        Ok(self.twitch.send_to(&self.channel, message).await?)
+    }
+}
+
+/// Sends every message as a `/me` action, which Twitch shows in italics or
+/// in the color of the bot.
+pub struct ActionSink {
+    pub twitch: Arc<Twitch>,
+    pub channel: String,
+}
+
+#[async_trait]
+impl MessageSink for ActionSink {
+    async fn send(&self, message: String) -> Result<(), Error> {
+        Ok(self.twitch.me_to(&self.channel, message).await?)
+    }
+
+    /// Replies are only possible in the channel commands are read from.
+    async fn reply(&self, message_id: String, message: String) -> Result<(), Error> {
+        if self.channel != self.twitch.channel() {
+            return self.send(message).await;
+        }
+
+        Ok(self.twitch.me_reply(message_id, message).await?)
+    }
+
+    /// Whispers can't be actions.
+    async fn whisper(&self, user_id: &str, message: String) -> Result<(), Error> {
+        Ok(self.twitch.whisper(user_id, message).await?)
+    }
+}
+
+#[async_trait]
+impl MessageSink for Discord {
+    async fn send(&self, message: String) -> Result<(), Error> {
+        Ok(Discord::send(self, &message).await?)
+    }
+}
+
+/// Appends every message as a line to a file, prefixed with the unix
+/// timestamp.
+pub struct FileSink {
+    pub path: String,
+}
+
+#[async_trait]
+impl MessageSink for FileSink {
+    async fn send(&self, message: String) -> Result<(), Error> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(Error::File)?;
+
+        file.write_all(format!("{} {}\n", timestamp, message).as_bytes())
+            .await
+            .map_err(Error::File)
+    }
+}
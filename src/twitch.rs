@@ -1,49 +1,165 @@
-use std::sync::{Arc, Weak};
+use std::{
+    convert::Infallible,
+    path::PathBuf,
+    sync::{Arc, Mutex, Weak},
+};
 
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, RwLock};
 use tokio::task::JoinHandle;
-use tracing::{error, info};
+use tokio::time::{self, Duration, Instant};
+use tracing::{debug, error, info, warn};
 use twitch_irc::{
-    login::StaticLoginCredentials,
+    login::{CredentialsPair, LoginCredentials},
     message::{self, ServerMessage},
     transport::tcp::{TCPTransport, TLS},
     ClientConfig, SecureTCPTransport, TwitchIRCClient,
 };
 
-use crate::{config, error::Error};
+use crate::{config, error::Error, CommandMessage, Settings};
+
+const TWITCH_VALIDATE_URL: &str = "https://id.twitch.tv/oauth2/validate";
+const TWITCH_TOKEN_URL: &str = "https://id.twitch.tv/oauth2/token";
+const TWITCH_WHISPERS_URL: &str = "https://api.twitch.tv/helix/whispers";
+const REQUIRED_SCOPES: [&str; 2] = ["chat:read", "chat:edit"];
+/// Refresh the token this many seconds before it expires.
+const REFRESH_MARGIN: u64 = 300;
 
 #[derive(Error, Debug)]
 pub enum TwitchError {
     #[error("disconnected from twitch")]
     Disconnected,
     #[error("twitch error")]
-    TwitchIrc(#[from] twitch_irc::Error<TCPTransport<TLS>, StaticLoginCredentials>),
+    TwitchIrc(#[from] twitch_irc::Error<TCPTransport<TLS>, Credentials>),
+    #[error("twitch api error")]
+    Api(#[from] reqwest::Error),
+    #[error("invalid oauth token: {0}")]
+    InvalidToken(String),
+}
+
+/// Response of the twitch oauth validate endpoint.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ValidateToken {
+    pub client_id: String,
+    pub login: String,
+    pub user_id: String,
+    pub scopes: Vec<String>,
+    pub expires_in: u64,
+}
+
+/// Response of the twitch oauth token endpoint when refreshing a token.
+#[derive(Debug, Clone, Deserialize)]
+struct RefreshedToken {
+    access_token: String,
+    refresh_token: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Whisper {
+    message: String,
+}
+
+/// Chat login credentials, the token gets replaced when it is refreshed so
+/// new connections always use the latest token.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    login: String,
+    token: Arc<RwLock<String>>,
+}
+
+#[async_trait]
+impl LoginCredentials for Credentials {
+    type Error = Infallible;
+
+    async fn get_credentials(&self) -> Result<CredentialsPair, Infallible> {
+        Ok(CredentialsPair {
+            login: self.login.to_owned(),
+            token: Some(self.token.read().await.to_owned()),
+        })
+    }
+}
+
+/// Everything needed to refresh the oauth token.
+#[derive(Debug, Clone)]
+struct Refresh {
+    client_id: String,
+    client_secret: String,
+    refresh_token: String,
+    /// The config file the refreshed tokens are saved to
+    config_path: PathBuf,
 }
 
 #[derive(Debug, Clone)]
 pub struct HandleMessage {
     pub channel_name: String,
+    pub sender_id: String,
     pub sender_name: String,
     pub broadcaster: bool,
     pub moderator: bool,
     pub vip: bool,
+    pub subscriber: bool,
+    pub message_id: String,
     pub message: String,
 }
 
+/// The last message sent, to not send the same message twice in a row.
+#[derive(Debug)]
+struct LastMessage {
+    window: Duration,
+    last: Option<(String, String, Instant)>,
+}
+
+impl LastMessage {
+    fn new(window: Duration) -> Self {
+        Self { window, last: None }
+    }
+
+    /// Returns true when the message is the same as the previous one in the
+    /// channel within the window, otherwise it becomes the previous one.
+    fn is_duplicate(&mut self, channel: &str, message: &str, now: Instant) -> bool {
+        if let Some((last_channel, last_message, sent)) = &self.last {
+            if last_channel == channel
+                && last_message == message
+                && now.duration_since(*sent) < self.window
+            {
+                return true;
+            }
+        }
+
+        self.last = Some((channel.to_owned(), message.to_owned(), now));
+        false
+    }
+}
+
 pub struct Twitch {
     pub read_handle: JoinHandle<()>,
-    pub client: TwitchIRCClient<TCPTransport<TLS>, StaticLoginCredentials>,
+    pub client: TwitchIRCClient<TCPTransport<TLS>, Credentials>,
     message_tx: Weak<broadcast::Sender<HandleMessage>>,
     channel: String,
+    credentials: Credentials,
+    http: reqwest::Client,
+    last_message: Mutex<LastMessage>,
 }
 
 impl Twitch {
-    pub async fn run(settings: config::Twitch) -> Result<Self, Error> {
+    /// Connects to Twitch chat, the client reconnects on its own at most once
+    /// every min reconnect delay.
+    pub async fn run(
+        settings: config::Twitch,
+        reconnect: &config::Reconnect,
+        config_path: PathBuf,
+    ) -> Result<Self, Error> {
         let config::Twitch {
             bot_username,
             bot_oauth,
             channel,
+            client_id,
+            client_secret,
+            refresh_token,
+            monitor_channel,
+            duplicate_window,
             ..
         } = settings;
 
@@ -55,10 +171,64 @@ impl Twitch {
             oauth = strip_oauth.to_string();
         }
 
-        let twitch_credentials = StaticLoginCredentials::new(username, Some(oauth));
-        let twitch_config = ClientConfig::new_simple(twitch_credentials);
+        let mut refresh = match (client_id, client_secret, refresh_token) {
+            (Some(client_id), Some(client_secret), Some(refresh_token)) => Some(Refresh {
+                client_id,
+                client_secret,
+                refresh_token,
+                config_path,
+            }),
+            _ => None,
+        };
+
+        // Make sure the token works before connecting, twitch only tells us
+        // about it after the connection has been made.
+        let http = reqwest::Client::new();
+        let token = match (validate_token(&http, &oauth).await, &mut refresh) {
+            (Err(TwitchError::InvalidToken(_)), Some(refresh)) => {
+                info!("Oauth token expired, refreshing");
+                oauth = refresh.refresh(&http).await?;
+                validate_token(&http, &oauth).await?
+            }
+            (token, _) => token?,
+        };
+
+        let missing = missing_scopes(&token.scopes);
+        if !missing.is_empty() {
+            return Err(TwitchError::InvalidToken(format!(
+                "missing the {} scope(s), the token has: {}",
+                missing.join(", "),
+                token.scopes.join(", ")
+            ))
+            .into());
+        }
+
+        if token.expires_in > 0 {
+            info!(
+                login = %token.login,
+                "Oauth token expires in {} hours",
+                token.expires_in / 3600
+            );
+        }
+
+        let credentials = Credentials {
+            login: username,
+            token: Arc::new(RwLock::new(oauth)),
+        };
+
+        if let (Some(refresh), true) = (refresh, token.expires_in > 0) {
+            tokio::spawn(refresh_loop(
+                http.clone(),
+                refresh,
+                credentials.clone(),
+                token.expires_in,
+            ));
+        }
+
+        let mut twitch_config = ClientConfig::new_simple(credentials.clone());
+        twitch_config.new_connection_every = Duration::from_secs(reconnect.min_delay);
         let (mut incoming_messages, client) =
-            TwitchIRCClient::<SecureTCPTransport, StaticLoginCredentials>::new(twitch_config);
+            TwitchIRCClient::<SecureTCPTransport, Credentials>::new(twitch_config);
 
         info!("Connected");
 
@@ -66,18 +236,28 @@ impl Twitch {
         let message_tx = Arc::new(tx);
 
         let tx_read = message_tx.clone();
+        let command_channel = channel.to_owned();
         let read_handle = tokio::spawn(async move {
             while let Some(message) = incoming_messages.recv().await {
                 match message {
                     ServerMessage::Notice(msg) => {
-                        error!("{}", msg.message_text);
+                        // Reconnecting won't help with bad credentials
                         if msg.message_text == "Login authentication failed" {
+                            error!("Twitch login failed, check the bot username and oauth");
                             break;
                         }
+
+                        error!("{}", msg.message_text);
                     }
-                    ServerMessage::Privmsg(msg) => {
+                    // Commands are only read from the channel, not the monitor channel
+                    ServerMessage::Privmsg(msg) if msg.channel_login == command_channel => {
                         let _ = tx_read.send(HandleMessage::from(msg));
                     }
+                    // Sent before server maintenance, the client closes the
+                    // connection and rejoins the channel on a new one
+                    ServerMessage::Reconnect(_) => {
+                        info!("Twitch requested a reconnect, reconnecting");
+                    }
                     _ => (),
                 }
             }
@@ -85,11 +265,18 @@ impl Twitch {
 
         client.join(channel.to_owned())?;
 
+        if !monitor_channel.is_empty() && monitor_channel != channel {
+            client.join(monitor_channel.to_lowercase())?;
+        }
+
         Ok(Self {
             client,
             read_handle,
             message_tx: Arc::downgrade(&message_tx),
             channel,
+            credentials,
+            http,
+            last_message: Mutex::new(LastMessage::new(Duration::from_secs(duplicate_window))),
         })
     }
 
@@ -100,37 +287,341 @@ impl Twitch {
     }
 
     pub async fn send(&self, message: String) -> Result<(), TwitchError> {
+        if self.is_duplicate(&self.channel, &message) {
+            return Ok(());
+        }
+
         self.client
             .say(self.channel.to_owned(), message)
             .await
             .map_err(TwitchError::TwitchIrc)
     }
+
+    /// Sends a message in another channel the bot joined.
+    pub async fn send_to(&self, channel: &str, message: String) -> Result<(), TwitchError> {
+        if self.is_duplicate(channel, &message) {
+            return Ok(());
+        }
+
+        self.client
+            .say(channel.to_lowercase(), message)
+            .await
+            .map_err(TwitchError::TwitchIrc)
+    }
+
+    /// Sends a `/me` action in the given channel.
+    pub async fn me_to(&self, channel: &str, message: String) -> Result<(), TwitchError> {
+        if self.is_duplicate(channel, &message) {
+            return Ok(());
+        }
+
+        self.client
+            .me(channel.to_lowercase(), message)
+            .await
+            .map_err(TwitchError::TwitchIrc)
+    }
+
+    /// Sends a `/me` action in reply to the message with the given id.
+    pub async fn me_reply(&self, message_id: String, message: String) -> Result<(), TwitchError> {
+        self.client
+            .me_in_reply_to(&(self.channel.to_owned(), message_id), message)
+            .await
+            .map_err(TwitchError::TwitchIrc)
+    }
+
+    /// Twitch rejects a message identical to the previous one anyway, so it
+    /// is not sent again within the duplicate window.
+    fn is_duplicate(&self, channel: &str, message: &str) -> bool {
+        let duplicate = self.last_message.lock().unwrap().is_duplicate(
+            &channel.to_lowercase(),
+            message,
+            Instant::now(),
+        );
+
+        if duplicate {
+            debug!(channel, message, "Suppressed duplicate message");
+        }
+
+        duplicate
+    }
+
+    /// Joins another channel to send messages in.
+    pub fn join(&self, channel: &str) -> Result<(), Error> {
+        Ok(self.client.join(channel.to_lowercase())?)
+    }
+
+    /// The channel commands are read from.
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Sends a message in reply to the message with the given id.
+    pub async fn reply(&self, message_id: String, message: String) -> Result<(), TwitchError> {
+        self.client
+            .say_in_reply_to(&(self.channel.to_owned(), message_id), message)
+            .await
+            .map_err(TwitchError::TwitchIrc)
+    }
+
+    /// Validates the oauth token of the bot.
+    pub async fn validate(&self) -> Result<ValidateToken, TwitchError> {
+        let oauth = self.credentials.token.read().await.to_owned();
+
+        validate_token(&self.http, &oauth).await
+    }
+
+    /// Sends a whisper to the user with the given id.
+    ///
+    /// The oauth token requires the `user:manage:whispers` scope.
+    pub async fn whisper(&self, user_id: &str, message: String) -> Result<(), TwitchError> {
+        let token = self.validate().await?;
+        let oauth = self.credentials.token.read().await.to_owned();
+
+        self.http
+            .post(TWITCH_WHISPERS_URL)
+            .query(&[
+                ("from_user_id", token.user_id.as_str()),
+                ("to_user_id", user_id),
+            ])
+            .bearer_auth(oauth)
+            .header("Client-Id", token.client_id)
+            .json(&Whisper { message })
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
 }
 
-impl From<message::PrivmsgMessage> for HandleMessage {
-    fn from(m: message::PrivmsgMessage) -> Self {
-        let broadcaster = m.badges.contains(&message::Badge {
-            name: "broadcaster".to_string(),
-            version: "1".to_string(),
-        });
+async fn validate_token(http: &reqwest::Client, oauth: &str) -> Result<ValidateToken, TwitchError> {
+    let response = http
+        .get(TWITCH_VALIDATE_URL)
+        .header("Authorization", format!("OAuth {}", oauth))
+        .send()
+        .await?;
 
-        let moderator = m.badges.contains(&message::Badge {
-            name: "moderator".to_string(),
-            version: "1".to_string(),
-        });
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        return Err(TwitchError::InvalidToken(
+            "the token is invalid or has expired, please generate a new one".to_string(),
+        ));
+    }
+
+    let token = response.error_for_status()?.json().await?;
+
+    Ok(token)
+}
+
+impl Refresh {
+    /// Refreshes the oauth token and saves the new tokens to the config.
+    async fn refresh(&mut self, http: &reqwest::Client) -> Result<String, TwitchError> {
+        let token: RefreshedToken = http
+            .post(TWITCH_TOKEN_URL)
+            .form(&[
+                ("grant_type", "refresh_token"),
+                ("refresh_token", &self.refresh_token),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        self.refresh_token = token.refresh_token.to_owned();
 
-        let vip = m.badges.contains(&message::Badge {
-            name: "vip".to_string(),
-            version: "1".to_string(),
+        let saved = Settings::update_file(&self.config_path, |settings| {
+            settings.twitch.bot_oauth = format!("oauth:{}", token.access_token);
+            settings.twitch.refresh_token = Some(token.refresh_token);
         });
 
+        if let Err(e) = saved {
+            warn!(?e, "failed to save the refreshed oauth token");
+        }
+
+        Ok(token.access_token)
+    }
+}
+
+/// Keeps refreshing the oauth token before it expires.
+async fn refresh_loop(
+    http: reqwest::Client,
+    mut refresh: Refresh,
+    credentials: Credentials,
+    mut expires_in: u64,
+) {
+    loop {
+        time::sleep(Duration::from_secs(
+            expires_in.saturating_sub(REFRESH_MARGIN),
+        ))
+        .await;
+
+        let token = match refresh.refresh(&http).await {
+            Ok(t) => t,
+            Err(e) => {
+                error!(
+                    ?e,
+                    "failed to refresh oauth token, trying again in a minute"
+                );
+                expires_in = REFRESH_MARGIN + 60;
+                continue;
+            }
+        };
+
+        expires_in = match validate_token(&http, &token).await {
+            Ok(v) => v.expires_in,
+            Err(e) => {
+                error!(?e, "failed to validate the refreshed oauth token");
+                REFRESH_MARGIN + 60
+            }
+        };
+
+        // The current chat connection stays logged in, reconnects use the
+        // new token.
+        *credentials.token.write().await = token;
+        info!("Refreshed oauth token");
+    }
+}
+
+/// Returns the scopes needed for chat which the token doesn't have.
+fn missing_scopes(scopes: &[String]) -> Vec<&'static str> {
+    REQUIRED_SCOPES
+        .into_iter()
+        .filter(|required| !scopes.iter().any(|s| s == required))
+        .collect()
+}
+
+impl CommandMessage for HandleMessage {
+    fn channel(&self) -> &str {
+        &self.channel_name
+    }
+
+    fn sender_id(&self) -> &str {
+        &self.sender_id
+    }
+
+    fn sender_name(&self) -> &str {
+        &self.sender_name
+    }
+
+    fn is_broadcaster(&self) -> bool {
+        self.broadcaster
+    }
+
+    fn is_moderator(&self) -> bool {
+        self.moderator
+    }
+
+    fn is_vip(&self) -> bool {
+        self.vip
+    }
+
+    fn is_subscriber(&self) -> bool {
+        self.subscriber
+    }
+
+    fn text(&self) -> &str {
+        &self.message
+    }
+
+    fn message_id(&self) -> Option<&str> {
+        Some(&self.message_id)
+    }
+}
+
+impl From<message::PrivmsgMessage> for HandleMessage {
+    fn from(m: message::PrivmsgMessage) -> Self {
+        // The version is e.g. the months subscribed, only the name matters
+        let has_badge = |name: &str| m.badges.iter().any(|b| b.name == name);
+
+        let broadcaster = has_badge("broadcaster");
+        let moderator = has_badge("moderator");
+        let vip = has_badge("vip");
+        // Founders get their own badge instead of the subscriber badge
+        let subscriber = has_badge("subscriber") || has_badge("founder");
+
         Self {
             channel_name: m.channel_login,
+            sender_id: m.sender.id,
             sender_name: m.sender.login,
             broadcaster,
             moderator,
             vip,
+            subscriber,
+            message_id: m.message_id,
             message: m.message_text,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_token() {
+        let response = r#"{"client_id":"wbmytr93xzw8zbg0p1izqyzzc5mbiz","login":"twitchdev","scopes":["chat:read","chat:edit"],"user_id":"141981764","expires_in":5520838}"#;
+
+        let token: ValidateToken = serde_json::from_str(response).unwrap();
+
+        assert_eq!(token.login, "twitchdev");
+        assert_eq!(token.expires_in, 5520838);
+        assert!(missing_scopes(&token.scopes).is_empty());
+    }
+
+    #[test]
+    fn duplicate_messages() {
+        let mut last = LastMessage::new(Duration::from_secs(30));
+        let now = Instant::now();
+
+        assert!(!last.is_duplicate("channel", "usb0 disconnected", now));
+        assert!(last.is_duplicate("channel", "usb0 disconnected", now));
+        assert!(!last.is_duplicate("mods", "usb0 disconnected", now));
+        assert!(!last.is_duplicate("mods", "usb1 disconnected", now));
+        assert!(!last.is_duplicate("mods", "usb1 disconnected", now + Duration::from_secs(30)));
+
+        let mut last = LastMessage::new(Duration::ZERO);
+        assert!(!last.is_duplicate("channel", "usb0 disconnected", now));
+        assert!(!last.is_duplicate("channel", "usb0 disconnected", now));
+    }
+
+    fn privmsg(badges: &str) -> HandleMessage {
+        let raw = format!(
+            "@badge-info=;badges={};color=;display-name=715209;emotes=;id=1;room-id=2;tmi-sent-ts=0;user-id=3 \
+             :715209!715209@715209.tmi.twitch.tv PRIVMSG #715209 :!bbs",
+            badges
+        );
+        let irc = twitch_irc::message::IRCMessage::parse(&raw).unwrap();
+
+        message::PrivmsgMessage::try_from(irc).unwrap().into()
+    }
+
+    #[test]
+    fn badges_with_any_version() {
+        let hm = privmsg("broadcaster/2,subscriber/3012");
+        assert!(hm.broadcaster && hm.subscriber);
+        assert!(!hm.moderator && !hm.vip);
+
+        let hm = privmsg("moderator/2,founder/0");
+        assert!(hm.moderator && hm.subscriber);
+
+        // Founders don't have the subscriber badge as well
+        let hm = privmsg("founder/0");
+        assert!(hm.subscriber && hm.is_subscriber());
+        assert!(!hm.moderator && !hm.vip);
+
+        let hm = privmsg("vip/1");
+        assert!(hm.vip && !hm.subscriber);
+
+        let hm = privmsg("");
+        assert!(!hm.broadcaster && !hm.moderator && !hm.vip && !hm.subscriber);
+    }
+
+    #[test]
+    fn missing_chat_scopes() {
+        let scopes = vec!["chat:read".to_string(), "user:manage:whispers".to_string()];
+
+        assert_eq!(missing_scopes(&scopes), vec!["chat:edit"]);
+    }
+}